@@ -0,0 +1,100 @@
+// What happens to cells moving past the edges of the grid. Every edge has its own mode, so a scene
+// can for example pour sand out of the bottom while the sides stay closed.
+//
+// The modes only apply to loose cells. Rigid bodies always see walls at the edges, whatever the
+// mode, so a crate rests on a void floor and doesn't wrap around to the other side.
+
+use crate::GRID_WIDTH;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Boundary {
+    Wall, // Cells stop at the edge
+    Void, // Cells leaving the grid are deleted
+    Wrap // Cells come back in on the opposite side
+}
+
+impl Boundary {
+    pub fn from_name(name: &str) -> Option<Boundary> {
+        match name {
+            "wall" => { Some(Boundary::Wall) }
+            "void" => { Some(Boundary::Void) }
+            "wrap" => { Some(Boundary::Wrap) }
+            _ => { None }
+        }
+    }
+
+    // For cycling through the modes with a key
+    pub fn next(&self) -> Boundary {
+        match self {
+            Boundary::Wall => { Boundary::Void }
+            Boundary::Void => { Boundary::Wrap }
+            Boundary::Wrap => { Boundary::Wall }
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Boundary::Wall => { "WALL" }
+            Boundary::Void => { "VOID" }
+            Boundary::Wrap => { "WRAP" }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Boundaries {
+    pub left: Boundary,
+    pub right: Boundary,
+    pub top: Boundary,
+    pub bottom: Boundary
+}
+
+impl Default for Boundaries {
+    fn default() -> Self {
+        Boundaries::all(Boundary::Wall)
+    }
+}
+
+// Where a cell stepping onto a point ends up
+pub enum Crossing {
+    Inside((i32, i32)),
+    Blocked,
+    Gone
+}
+
+impl Boundaries {
+    pub fn all(boundary: Boundary) -> Boundaries {
+        Boundaries { left: boundary, right: boundary, top: boundary, bottom: boundary }
+    }
+
+    // Points inside the grid stay where they are, a void edge wins over a wall in the corners
+    pub fn resolve(&self, point: (i32, i32)) -> Crossing {
+        let x = Self::resolve_axis(point.0, self.left, self.right);
+        let y = Self::resolve_axis(point.1, self.top, self.bottom);
+        match (x, y) {
+            (Ok(x), Ok(y)) => { Crossing::Inside((x, y)) }
+            (Err(Boundary::Void), _) | (_, Err(Boundary::Void)) => { Crossing::Gone }
+            _ => { Crossing::Blocked }
+        }
+    }
+
+    // Whether the neighbour of the cell in the given direction is past a void edge
+    pub fn leads_into_void(&self, pos: usize, dir: (i8, i8)) -> bool {
+        let point = ((pos % GRID_WIDTH) as i32 + dir.0 as i32, (pos / GRID_WIDTH) as i32 + dir.1 as i32);
+        matches!(self.resolve(point), Crossing::Gone)
+    }
+
+    fn resolve_axis(value: i32, low: Boundary, high: Boundary) -> Result<i32, Boundary> {
+        let edge = if value < 0 {
+            low
+        } else if value >= GRID_WIDTH as i32 {
+            high
+        } else {
+            return Ok(value);
+        };
+        match edge {
+            Boundary::Wrap => { Ok(value.rem_euclid(GRID_WIDTH as i32)) }
+            _ => { Err(edge) }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{BUFFER_WIDTH, GRID_WIDTH};
+
+// Smallest zoom still shows the whole grid, bigger grids than the buffer are scaled down
+const MIN_ZOOM: f32 = BUFFER_WIDTH as f32 / GRID_WIDTH as f32;
+const MAX_ZOOM: f32 = 8.0;
+const ZOOM_STEP: f32 = 1.25;
+
+// Maps pixels of the frame buffer to cells of the grid
+pub struct Camera {
+    offset: (f32, f32), // Grid position of the top-left pixel
+    zoom: f32 // Pixels per cell
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            offset: (0.0, 0.0),
+            zoom: MIN_ZOOM
+        }
+    }
+}
+
+impl Camera {
+    pub fn to_grid(&self, pixel: (usize, usize)) -> (usize, usize) {
+        let x = self.offset.0 + (pixel.0 as f32 + 0.5) / self.zoom;
+        let y = self.offset.1 + (pixel.1 as f32 + 0.5) / self.zoom;
+        (
+            (x.max(0.0) as usize).min(GRID_WIDTH - 1),
+            (y.max(0.0) as usize).min(GRID_WIDTH - 1)
+        )
+    }
+
+    // Zooms by the given amount of wheel steps while keeping the cell under the pixel in place
+    pub fn zoom_at(&mut self, pixel: (f32, f32), steps: f32) {
+        let anchor = (self.offset.0 + pixel.0 / self.zoom, self.offset.1 + pixel.1 / self.zoom);
+        self.zoom = (self.zoom * ZOOM_STEP.powf(steps)).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = (anchor.0 - pixel.0 / self.zoom, anchor.1 - pixel.1 / self.zoom);
+        self.clamp();
+    }
+
+    // Moves the view so the grid follows a drag of the given amount of pixels
+    pub fn pan(&mut self, delta: (f32, f32)) {
+        self.offset.0 -= delta.0 / self.zoom;
+        self.offset.1 -= delta.1 / self.zoom;
+        self.clamp();
+    }
+
+    fn clamp(&mut self) {
+        let visible = BUFFER_WIDTH as f32 / self.zoom;
+        let max_offset = (GRID_WIDTH as f32 - visible).max(0.0);
+        self.offset.0 = self.offset.0.clamp(0.0, max_offset);
+        self.offset.1 = self.offset.1.clamp(0.0, max_offset);
+    }
+}
@@ -0,0 +1,158 @@
+// The cells of the grid, stored as one array per property. Passes that only need some of the
+// properties, like skipping air in the logic loop or drawing, don't read the rest. Materials are
+// stored as their CellType::index and colors without the alpha channel, cells are always opaque.
+// Ages are kept as the tick a cell was created at, so cells get older without being visited, and
+// the cells with a lifetime are listed so decaying them doesn't go through the whole grid.
+
+use crate::{Cell, CellType};
+
+// CellType::BUILTIN starts with air
+const AIR: u16 = 0;
+// Slot of a cell without a lifetime
+const UNTIMED: u32 = u32::MAX;
+
+pub struct Cells {
+    materials: Vec<u16>,
+    pub velocity: Vec<(f32, f32)>,
+    pub remainder: Vec<(f32, f32)>,
+    pub free_falling: Vec<u8>,
+    pub moved: Vec<bool>,
+    pub grounded: Vec<bool>,
+    colors: Vec<[u8; 3]>,
+    pub temperature: Vec<f32>,
+    pub pressure: Vec<f32>,
+    born: Vec<u64>, // Tick the cell was created at
+    lifetime: Vec<Option<u16>>,
+    tick: u64,
+    timed: Vec<usize>, // Positions of the cells with a lifetime, in no particular order
+    timed_slots: Vec<u32> // Where each cell is in timed
+}
+
+impl Cells {
+    pub fn filled(cell: Cell, size: usize) -> Cells {
+        let timed: Vec<usize> = if cell.lifetime.is_some() { (0..size).collect() } else { vec![] };
+        Cells {
+            materials: vec![CellType::index(cell.cell_type) as u16; size],
+            velocity: vec![cell.velocity; size],
+            remainder: vec![cell.remainder; size],
+            free_falling: vec![cell.free_falling; size],
+            moved: vec![cell.moved; size],
+            grounded: vec![cell.grounded; size],
+            colors: vec![[cell.color[0], cell.color[1], cell.color[2]]; size],
+            temperature: vec![cell.temperature; size],
+            pressure: vec![cell.pressure; size],
+            born: vec![0u64.wrapping_sub(cell.age as u64); size],
+            lifetime: vec![cell.lifetime; size],
+            tick: 0,
+            timed_slots: if timed.is_empty() { vec![UNTIMED; size] } else { (0..size as u32).collect() },
+            timed
+        }
+    }
+
+    // Copies the cell out, for code that works on whole cells
+    pub fn get(&self, pos: usize) -> Cell {
+        Cell {
+            cell_type: self.material(pos),
+            velocity: self.velocity[pos],
+            remainder: self.remainder[pos],
+            free_falling: self.free_falling[pos],
+            moved: self.moved[pos],
+            grounded: self.grounded[pos],
+            color: self.color(pos),
+            temperature: self.temperature[pos],
+            pressure: self.pressure[pos],
+            age: self.age(pos),
+            lifetime: self.lifetime[pos]
+        }
+    }
+
+    pub fn set(&mut self, pos: usize, cell: Cell) {
+        self.materials[pos] = CellType::index(cell.cell_type) as u16;
+        self.velocity[pos] = cell.velocity;
+        self.remainder[pos] = cell.remainder;
+        self.free_falling[pos] = cell.free_falling;
+        self.moved[pos] = cell.moved;
+        self.grounded[pos] = cell.grounded;
+        self.set_color(pos, cell.color);
+        self.temperature[pos] = cell.temperature;
+        self.pressure[pos] = cell.pressure;
+        self.born[pos] = self.tick.wrapping_sub(cell.age as u64);
+        self.lifetime[pos] = cell.lifetime;
+
+        let slot = self.timed_slots[pos];
+        if slot == UNTIMED && cell.lifetime.is_some() {
+            self.timed_slots[pos] = self.timed.len() as u32;
+            self.timed.append(&mut vec![pos]);
+        } else if slot != UNTIMED && cell.lifetime.is_none() {
+            // The last cell in the list takes its place
+            self.timed.swap_remove(slot as usize);
+            if let Some(&moved) = self.timed.get(slot as usize) {
+                self.timed_slots[moved] = slot;
+            }
+            self.timed_slots[pos] = UNTIMED;
+        }
+    }
+
+    // Both cells count as moved afterwards
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.materials.swap(a, b);
+        self.velocity.swap(a, b);
+        self.remainder.swap(a, b);
+        self.free_falling.swap(a, b);
+        self.grounded.swap(a, b);
+        self.colors.swap(a, b);
+        self.temperature.swap(a, b);
+        self.pressure.swap(a, b);
+        self.born.swap(a, b);
+        self.lifetime.swap(a, b);
+        self.timed_slots.swap(a, b);
+        for pos in [a, b] {
+            if self.timed_slots[pos] != UNTIMED {
+                self.timed[self.timed_slots[pos] as usize] = pos;
+            }
+        }
+        self.moved[a] = true;
+        self.moved[b] = true;
+    }
+
+    pub fn material(&self, pos: usize) -> &'static CellType {
+        CellType::from_index(self.materials[pos] as usize)
+    }
+
+    pub fn is_air(&self, pos: usize) -> bool {
+        self.materials[pos] == AIR
+    }
+
+    // Materials of all cells by CellType::index, in grid order
+    pub fn material_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.materials.iter().map(|index| *index as usize)
+    }
+
+    pub fn color(&self, pos: usize) -> [u8; 4] {
+        let [r, g, b] = self.colors[pos];
+        [r, g, b, 255]
+    }
+
+    pub fn set_color(&mut self, pos: usize, color: [u8; 4]) {
+        self.colors[pos] = [color[0], color[1], color[2]];
+    }
+
+    // Ticks since the cell was created
+    pub fn age(&self, pos: usize) -> u16 {
+        self.tick.wrapping_sub(self.born[pos]).min(u16::MAX as u64) as u16
+    }
+
+    pub fn lifetime(&self, pos: usize) -> Option<u16> {
+        self.lifetime[pos]
+    }
+
+    // Every cell gets a tick older
+    pub fn advance_tick(&mut self) {
+        self.tick += 1;
+    }
+
+    // Positions of the cells with a lifetime
+    pub fn timed(&self) -> &[usize] {
+        &self.timed
+    }
+}
@@ -0,0 +1,128 @@
+// Radial impulses and the explosions built on them.
+//
+// An impulse pushes every movable cell and rigid body within its radius away from the center, with
+// a strength falling off linearly to zero at the edge. An explosion also destroys the cells close to
+// its center, breaks stone and wood into debris, heats up everything it reaches and detonates other
+// explosives caught in it.
+
+use crate::{Cell, CellType, Grid, CELL_AIR, GRID_WIDTH};
+
+// Part of the radius where cells are destroyed
+const CORE: f32 = 0.4;
+// Temperature added at the center of an explosion
+const EXPLOSION_HEAT: f32 = 600.0;
+// Push of explosions against gravity relative to the outward one. Buried cells can only get away upwards.
+const EXPLOSION_LIFT: f32 = 0.6;
+// Impulses add up, this keeps overlapping explosions from flinging cells through the whole grid
+const MAX_IMPULSE_SPEED: f32 = 12.0;
+
+const CELL_DEBRIS: CellType = CellType::Debris;
+
+impl Grid {
+    pub fn impulse(&mut self, center: (f32, f32), radius: f32, strength: f32) {
+        self.lifting_impulse(center, radius, strength, 0.0);
+    }
+
+    // Impulse with an extra push against gravity, relative to the outward one
+    fn lifting_impulse(&mut self, center: (f32, f32), radius: f32, strength: f32, lift: f32) {
+        let gravity = self.gravity.0.hypot(self.gravity.1);
+        let lift = if gravity > 0.0 { (-self.gravity.0 / gravity * lift, -self.gravity.1 / gravity * lift) } else { (0.0, 0.0) };
+        for pos in cells_within(center, radius) {
+            if !CellType::is_movable_solid(self.cells.material(pos), &self.materials) {
+                continue;
+            }
+            let push = push_from(center, cell_center(pos), radius, strength, lift);
+            let velocity = &mut self.cells.velocity[pos];
+            *velocity = (velocity.0 + push.0, velocity.1 + push.1);
+            let speed = velocity.0.hypot(velocity.1);
+            if speed > MAX_IMPULSE_SPEED {
+                *velocity = (velocity.0 / speed * MAX_IMPULSE_SPEED, velocity.1 / speed * MAX_IMPULSE_SPEED);
+            }
+            // Sleeping and grounded cells have to notice they are flying now
+            self.cells.free_falling[pos] = 0;
+            self.cells.grounded[pos] = false;
+        }
+
+        for body in &mut self.bodies {
+            body.push(push_from(center, body.position(), radius, strength, lift));
+        }
+    }
+
+    pub fn explode(&mut self, center: (f32, f32), radius: f32, strength: f32) {
+        let mut explosions = vec![center];
+        while let Some(center) = explosions.pop() {
+            for pos in cells_within(center, radius) {
+                let mut cell = self.cells.get(pos);
+                let distance = distance(center, cell_center(pos));
+                if distance <= radius * CORE {
+                    match cell.cell_type {
+                        CellType::Air => {}
+                        CellType::Explosive => {
+                            // The explosive going off is at the center, it only goes off once
+                            if cell_center(pos) != center {
+                                explosions.append(&mut vec![cell_center(pos)]);
+                            }
+                            cell = Cell::new(&CELL_AIR, &self.materials);
+                        }
+                        CellType::Debris => {}
+                        CellType::Stone | CellType::Wood => {
+                            let color = cell.color;
+                            cell = Cell::new(&CELL_DEBRIS, &self.materials);
+                            cell.color = color;
+                        }
+                        _ => {
+                            cell = Cell::new(&CELL_AIR, &self.materials);
+                        }
+                    }
+                }
+                if !cell.cell_type.eq(&CELL_AIR) {
+                    cell.temperature += EXPLOSION_HEAT * (1.0 - distance / radius);
+                }
+                self.cells.set(pos, cell);
+            }
+            self.lifting_impulse(center, radius, strength, EXPLOSION_LIFT);
+        }
+    }
+}
+
+fn cell_center(pos: usize) -> (f32, f32) {
+    ((pos % GRID_WIDTH) as f32, (pos / GRID_WIDTH) as f32)
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+// Velocity pointing away from the center, strongest at the center and zero at the radius
+fn push_from(center: (f32, f32), point: (f32, f32), radius: f32, strength: f32, lift: (f32, f32)) -> (f32, f32) {
+    let distance = distance(center, point);
+    if distance >= radius {
+        return (0.0, 0.0);
+    }
+    let falloff = strength * (1.0 - distance / radius);
+    if distance == 0.0 {
+        return (0.0, -falloff); // Straight up from the very center
+    }
+    (
+        ((point.0 - center.0) / distance + lift.0) * falloff,
+        ((point.1 - center.1) / distance + lift.1) * falloff
+    )
+}
+
+fn cells_within(center: (f32, f32), radius: f32) -> Vec<usize> {
+    let mut cells = vec![];
+    let min = ((center.0 - radius).floor().max(0.0) as usize, (center.1 - radius).floor().max(0.0) as usize);
+    let max = (
+        ((center.0 + radius).ceil().max(0.0) as usize).min(GRID_WIDTH - 1),
+        ((center.1 + radius).ceil().max(0.0) as usize).min(GRID_WIDTH - 1)
+    );
+    for y in min.1..=max.1 {
+        for x in min.0..=max.0 {
+            let pos = y * GRID_WIDTH + x;
+            if distance(center, cell_center(pos)) < radius {
+                cells.append(&mut vec![pos]);
+            }
+        }
+    }
+    cells
+}
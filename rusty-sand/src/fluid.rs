@@ -0,0 +1,231 @@
+// Liquids and gases. Liquids fall and spread out, and carry the pressure of the liquid above them:
+// a connected body of liquid moves cells from its top to its lowest free surface, so the
+// levels in U-tubes and communicating vessels even out. Gases spread into free cells, towards the
+// ones with the least gas around them, so packed gas rushes out of a chamber once it is opened.
+
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::{Cell, CellType, Changes, GravityFrame, Grid, CELL_AIR, GRID_SIZE, GRID_WIDTH};
+
+// Most cells moved from the top to the lowest surface of a body of liquid each tick
+const EQUALIZE_RATE: usize = 4;
+// Pressure of gas packed on every side, in cells of liquid depth
+const GAS_PRESSURE: f32 = 10.0;
+// Chance of gas in open air to move each tick, packed gas always moves
+const GAS_DIFFUSION: f64 = 0.3;
+// Chance of gas to follow a force field each tick, per unit of its acceleration
+const GAS_FORCE_RESPONSE: f32 = 1.5;
+
+const SIDES: [(i8, i8); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+impl Cell {
+    // Falls, slides down diagonally, or flows sideways, pushing gases out of the way
+    pub fn liquid_step(grid: &mut Grid, pos: usize, changes: &mut Changes, rng: &mut StdRng) {
+        grid.cells.moved[pos] = false;
+        // Force fields add to gravity, so wind bends the flow and updrafts lift it
+        let force = grid.force_at((pos % GRID_WIDTH, pos / GRID_WIDTH));
+        let Some(frame) = GravityFrame::new((grid.gravity.0 + force.0, grid.gravity.1 + force.1)) else {
+            return;
+        };
+
+        for directions in [vec![frame.below], vec![frame.below_left, frame.below_right], vec![frame.left, frame.right]] {
+            let free: Vec<(i8, i8)> = directions
+                .into_iter()
+                .filter(|dir| Self::is_open(grid, pos, *dir, |n| n.eq(&CELL_AIR) || CellType::is_gas(n, &grid.materials)))
+                .collect();
+            if !free.is_empty() {
+                Self::flow(grid, pos, free[rng.random_range(0..free.len())], changes);
+                return;
+            }
+        }
+    }
+
+    // Moves into the free neighbour with the least gas around it, or the one force fields push it to
+    pub fn gas_step(grid: &mut Grid, pos: usize, changes: &mut Changes, rng: &mut StdRng) {
+        grid.cells.moved[pos] = false;
+        let force = grid.force_at((pos % GRID_WIDTH, pos / GRID_WIDTH));
+        let strength = force.0.hypot(force.1);
+        if strength > 0.0 && rng.random_bool((strength * GAS_FORCE_RESPONSE).min(1.0) as f64) {
+            if let Some(dir) = Self::free_direction_along(grid, pos, force) {
+                Self::flow(grid, pos, dir, changes);
+                return;
+            }
+        }
+        if !rng.random_bool(GAS_DIFFUSION.max((grid.cells.pressure[pos] / GAS_PRESSURE) as f64)) {
+            return;
+        }
+
+        let mut best = vec![];
+        let mut least_gas = usize::MAX;
+        for dir in (-1..=1).flat_map(|y| (-1..=1).map(move |x| (x, y))).filter(|dir| *dir != (0, 0)) {
+            if !Self::is_open(grid, pos, dir, |n| n.eq(&CELL_AIR)) {
+                continue;
+            }
+            // Nothing is known past a void edge, it counts as open air
+            let gas = match Self::get_neighbour(grid, pos, dir) {
+                (p, Some(_)) => { Self::get_neighbours(grid, p).iter().filter(|(_, n)| n.is_some_and(|n| CellType::is_gas(n, &grid.materials))).count() }
+                (_, None) => { 0 }
+            };
+            if gas < least_gas {
+                least_gas = gas;
+                best.clear();
+            }
+            if gas == least_gas {
+                best.append(&mut vec![dir]);
+            }
+        }
+        if !best.is_empty() {
+            Self::flow(grid, pos, best[rng.random_range(0..best.len())], changes);
+        }
+    }
+
+    // The free direction pointing the most along the given one, if any points along it at all
+    fn free_direction_along(grid: &Grid, pos: usize, direction: (f32, f32)) -> Option<(i8, i8)> {
+        let mut best = None;
+        let mut best_alignment = 0.0;
+        for dir in GravityFrame::DIRECTIONS {
+            if !Self::is_open(grid, pos, dir, |n| n.eq(&CELL_AIR)) {
+                continue;
+            }
+            let alignment = (dir.0 as f32 * direction.0 + dir.1 as f32 * direction.1) / (dir.0 as f32).hypot(dir.1 as f32);
+            if alignment > best_alignment {
+                best_alignment = alignment;
+                best = Some(dir);
+            }
+        }
+        best
+    }
+
+    // Whether the neighbour is one the cell can move into, the way out through a void edge always is
+    fn is_open(grid: &Grid, pos: usize, dir: (i8, i8), free: impl Fn(&CellType) -> bool) -> bool {
+        match Self::get_neighbour(grid, pos, dir) {
+            (_, Some(n)) => { free(n) }
+            (_, None) => { grid.boundaries.leads_into_void(pos, dir) }
+        }
+    }
+
+    // Moves the cell to the neighbour, or deletes it when it flows out through a void edge
+    fn flow(grid: &Grid, pos: usize, dir: (i8, i8), changes: &mut Changes) {
+        match Self::get_neighbour(grid, pos, dir) {
+            (p, Some(_)) => { changes.pos.append(&mut vec![(pos, p)]); }
+            (_, None) => { changes.removed.append(&mut vec![pos]); }
+        }
+    }
+}
+
+impl Grid {
+    pub fn update_pressure(&mut self) {
+        let frame = GravityFrame::new(self.gravity);
+        let mut visited = vec![false; GRID_SIZE];
+        let mut bodies = vec![];
+        for pos in 0..GRID_SIZE {
+            let cell_type = self.cells.material(pos);
+            if CellType::is_liquid(cell_type, &self.materials) {
+                if !visited[pos] {
+                    bodies.append(&mut vec![self.flood(pos, &mut visited)]);
+                }
+            } else if CellType::is_gas(cell_type, &self.materials) {
+                self.cells.pressure[pos] = self.gas_pressure(pos);
+            } else {
+                self.cells.pressure[pos] = 0.0;
+            }
+        }
+
+        // Settling moves liquid into free cells, so all bodies are found before any of them settles.
+        // A moved cell further along the grid would otherwise be taken for a body of its own.
+        for body in bodies {
+            match &frame {
+                Some(frame) => { self.settle_liquid(&body, frame); }
+                None => {
+                    // Nothing weighs on anything without gravity
+                    for pos in body {
+                        self.cells.pressure[pos] = 0.0;
+                    }
+                }
+            }
+        }
+    }
+
+    // Liquid cells connected to the given one
+    fn flood(&self, start: usize, visited: &mut [bool]) -> Vec<usize> {
+        let mut body = vec![start];
+        visited[start] = true;
+        let mut i = 0;
+        while i < body.len() {
+            let pos = body[i];
+            i += 1;
+            for dir in SIDES {
+                if let (p, Some(n)) = Cell::get_neighbour(self, pos, dir) {
+                    if !visited[p] && CellType::is_liquid(n, &self.materials) {
+                        visited[p] = true;
+                        body.append(&mut vec![p]);
+                    }
+                }
+            }
+        }
+        body
+    }
+
+    // Sets the pressure of every cell of the body to its depth below its top, then moves cells from
+    // the top on top of the lowest free surfaces
+    fn settle_liquid(&mut self, body: &[usize], frame: &GravityFrame) {
+        let level = |pos: usize| (pos % GRID_WIDTH) as i32 * frame.below.0 as i32 + (pos / GRID_WIDTH) as i32 * frame.below.1 as i32;
+        let above = (-frame.below.0, -frame.below.1);
+
+        // Cells without liquid on top, and the free cell on top of the ones that have air or gas there
+        let mut tops: Vec<usize> = vec![];
+        let mut surface: Vec<(usize, usize)> = vec![];
+        for &pos in body {
+            match Cell::get_neighbour(self, pos, above) {
+                (p, Some(n)) => {
+                    if n.eq(&CELL_AIR) || CellType::is_gas(n, &self.materials) {
+                        surface.append(&mut vec![(pos, p)]);
+                    }
+                    if !CellType::is_liquid(n, &self.materials) {
+                        tops.append(&mut vec![pos]);
+                    }
+                }
+                (_, None) => { tops.append(&mut vec![pos]); }
+            }
+        }
+        tops.sort_by_key(|pos| level(*pos));
+        surface.sort_by_key(|(pos, _)| std::cmp::Reverse(level(*pos)));
+
+        // A column wrapping around the top and bottom edges has no top, it doesn't settle anywhere
+        let Some(&top) = tops.first() else {
+            for &pos in body {
+                self.cells.pressure[pos] = 0.0;
+            }
+            return;
+        };
+        let top = level(top);
+        for &pos in body {
+            self.cells.pressure[pos] = (level(pos) - top) as f32;
+        }
+
+        for (&highest, &(lowest, on_lowest)) in tops.iter().zip(&surface).take(EQUALIZE_RATE) {
+            if level(lowest) - level(highest) <= 1 {
+                break;
+            }
+            self.cells.swap(highest, on_lowest);
+        }
+    }
+
+    // How packed the gas is, gas and walls around it both keep it from spreading
+    fn gas_pressure(&self, pos: usize) -> f32 {
+        let neighbours = Cell::get_neighbours(self, pos);
+        let mut packed = 0;
+        for (_, n) in neighbours {
+            match n {
+                Some(n) => {
+                    if !n.eq(&CELL_AIR) && (CellType::is_gas(n, &self.materials) || CellType::is_solid(n, &self.materials)) {
+                        packed += 1;
+                    }
+                }
+                None => { packed += 1; }
+            }
+        }
+        packed as f32 / neighbours.len() as f32 * GAS_PRESSURE
+    }
+}
@@ -0,0 +1,127 @@
+// Tiny 3x5 bitmap font used to draw text straight into the pixels frame.
+// Every glyph is 5 rows of 3 bits, the highest bit being the leftmost column.
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+pub const GLYPH_SPACING: usize = 1;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '~' => [0b000, 0b011, 0b110, 0b000, 0b000],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010] // '?'
+    }
+}
+
+pub fn text_width(text: &str) -> usize {
+    let len = text.chars().count();
+    if len == 0 {
+        return 0;
+    }
+    len * (GLYPH_WIDTH + GLYPH_SPACING) - GLYPH_SPACING
+}
+
+// Draws the text with its top-left corner at pos. Anything outside the frame is clipped.
+pub fn draw_text(frame: &mut [u8], frame_width: usize, pos: (usize, usize), text: &str, color: [u8; 4]) {
+    let frame_height = frame.len() / 4 / frame_width;
+
+    for (i, c) in text.chars().enumerate() {
+        let glyph_x = pos.0 + i * (GLYPH_WIDTH + GLYPH_SPACING);
+        for (row, bits) in glyph(c).iter().enumerate() {
+            let y = pos.1 + row;
+            if y >= frame_height {
+                break;
+            }
+            for column in 0..GLYPH_WIDTH {
+                let x = glyph_x + column;
+                if x >= frame_width {
+                    break;
+                }
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    let index = (y * frame_width + x) * 4;
+                    frame[index..index + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
+
+// Darkens the rectangle so text drawn on top of the simulation stays readable.
+pub fn shade_rect(frame: &mut [u8], frame_width: usize, pos: (usize, usize), size: (usize, usize)) {
+    let frame_height = frame.len() / 4 / frame_width;
+
+    for y in pos.1..(pos.1 + size.1).min(frame_height) {
+        for x in pos.0..(pos.0 + size.0).min(frame_width) {
+            let index = (y * frame_width + x) * 4;
+            for channel in &mut frame[index..index + 3] {
+                *channel /= 3;
+            }
+        }
+    }
+}
+
+pub fn fill_rect(frame: &mut [u8], frame_width: usize, pos: (usize, usize), size: (usize, usize), color: [u8; 4]) {
+    let frame_height = frame.len() / 4 / frame_width;
+
+    for y in pos.1..(pos.1 + size.1).min(frame_height) {
+        for x in pos.0..(pos.0 + size.0).min(frame_width) {
+            let index = (y * frame_width + x) * 4;
+            frame[index..index + 4].copy_from_slice(&color);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Rectangular regions adding an acceleration to the cells inside of them every tick, like wind,
+// updrafts and vortices. Rigid bodies are pushed when their center is inside. Liquids and gases
+// have no velocity, the fields bend the direction they flow in instead.
+
+use crate::camera::Camera;
+use crate::texture::blend_color;
+use crate::{CellType, Grid, BUFFER_WIDTH, GRID_WIDTH};
+
+// Distance between the arrows of the overlay, in cells
+const ARROW_SPACING: usize = 8;
+// Length of an arrow for an acceleration of 1
+const ARROW_SCALE: f32 = 12.0;
+const MAX_ARROW_LENGTH: f32 = 3.5;
+const OVERLAY_OPACITY: f32 = 0.3;
+
+#[derive(Copy, Clone)]
+pub enum Force {
+    Wind((f32, f32)),
+    // Turns around the center of the field, clockwise for positive strength
+    Vortex(f32)
+}
+
+pub struct ForceField {
+    position: (usize, usize), // Top-left cell
+    size: (usize, usize),
+    force: Force
+}
+
+impl ForceField {
+    // Fields are cut off at the edges of the grid
+    pub fn new(center: (usize, usize), size: (usize, usize), force: Force) -> ForceField {
+        let position = (center.0.saturating_sub(size.0 / 2), center.1.saturating_sub(size.1 / 2));
+        ForceField {
+            position,
+            size: (size.0.min(GRID_WIDTH - position.0), size.1.min(GRID_WIDTH - position.1)),
+            force
+        }
+    }
+
+    fn contains(&self, pos: (usize, usize)) -> bool {
+        pos.0 >= self.position.0 && pos.0 < self.position.0 + self.size.0
+            && pos.1 >= self.position.1 && pos.1 < self.position.1 + self.size.1
+    }
+
+    fn center(&self) -> (f32, f32) {
+        (
+            self.position.0 as f32 + (self.size.0 - 1) as f32 / 2.0,
+            self.position.1 as f32 + (self.size.1 - 1) as f32 / 2.0
+        )
+    }
+
+    fn acceleration(&self, pos: (f32, f32)) -> (f32, f32) {
+        match self.force {
+            Force::Wind(acceleration) => { acceleration }
+            Force::Vortex(strength) => {
+                let center = self.center();
+                let offset = (pos.0 - center.0, pos.1 - center.1);
+                let distance = offset.0.hypot(offset.1);
+                if distance == 0.0 {
+                    return (0.0, 0.0);
+                }
+                // Along the circle, with a slight pull inwards to keep cells going around
+                (
+                    (-offset.1 - offset.0 * 0.5) / distance * strength,
+                    (offset.0 - offset.1 * 0.5) / distance * strength
+                )
+            }
+        }
+    }
+}
+
+impl Grid {
+    pub fn add_force_field(&mut self, field: ForceField) {
+        self.force_fields.append(&mut vec![field]);
+    }
+
+    // Sum of the accelerations of every field covering the cell
+    pub fn force_at(&self, pos: (usize, usize)) -> (f32, f32) {
+        let mut force = (0.0, 0.0);
+        for field in &self.force_fields {
+            if field.contains(pos) {
+                let acceleration = field.acceleration((pos.0 as f32, pos.1 as f32));
+                force = (force.0 + acceleration.0, force.1 + acceleration.1);
+            }
+        }
+        force
+    }
+
+    pub fn apply_force_fields(&mut self) {
+        for field in &self.force_fields {
+            for y in field.position.1..field.position.1 + field.size.1 {
+                for x in field.position.0..field.position.0 + field.size.0 {
+                    let pos = y * GRID_WIDTH + x;
+                    if !CellType::is_movable_solid(self.cells.material(pos), &self.materials) {
+                        continue;
+                    }
+                    let acceleration = field.acceleration((x as f32, y as f32));
+                    let velocity = &mut self.cells.velocity[pos];
+                    *velocity = (velocity.0 + acceleration.0, velocity.1 + acceleration.1);
+                    // Sleeping cells would ignore it
+                    self.cells.free_falling[pos] = 0;
+                }
+            }
+
+            for body in &mut self.bodies {
+                let position = body.position();
+                if position.0 >= 0.0 && position.1 >= 0.0 && field.contains((position.0.round() as usize, position.1.round() as usize)) {
+                    body.push(field.acceleration(position));
+                }
+            }
+        }
+    }
+}
+
+// Tints the cells inside force fields and draws an arrow pointing along the force every few cells
+pub fn draw_force_overlay(frame: &mut [u8], grid: &Grid, camera: &Camera) {
+    if grid.force_fields.is_empty() {
+        return;
+    }
+
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        let (x, y) = camera.to_grid((i % BUFFER_WIDTH, i / BUFFER_WIDTH));
+        if !grid.force_fields.iter().any(|field| field.contains((x, y))) {
+            continue;
+        }
+
+        // Arrows start at the middle of their block and get brighter towards the tip
+        let anchor = (x / ARROW_SPACING * ARROW_SPACING + ARROW_SPACING / 2, y / ARROW_SPACING * ARROW_SPACING + ARROW_SPACING / 2);
+        let force = grid.force_at((anchor.0.min(GRID_WIDTH - 1), anchor.1.min(GRID_WIDTH - 1)));
+        let magnitude = force.0.hypot(force.1);
+        let length = (magnitude * ARROW_SCALE).min(MAX_ARROW_LENGTH);
+        let mut color = [90, 200, 255, 255];
+        let mut opacity = OVERLAY_OPACITY;
+        if magnitude > 0.0 {
+            let direction = (force.0 / magnitude, force.1 / magnitude);
+            let offset = (x as f32 - anchor.0 as f32, y as f32 - anchor.1 as f32);
+            let along = offset.0 * direction.0 + offset.1 * direction.1;
+            let across = offset.0 * -direction.1 + offset.1 * direction.0;
+            if along >= 0.0 && along <= length && across.abs() < 0.5 {
+                color = [255, 255, 255, 255];
+                opacity = 0.4 + 0.6 * along / MAX_ARROW_LENGTH;
+            }
+        }
+
+        blend_color(pixel, color, opacity);
+    }
+}
@@ -0,0 +1,183 @@
+// Golden image tests for rendering. Fixed seeded scenes are drawn into an offscreen frame and
+// compared with the reference images in tests/golden. When a frame differs, the actual frame and
+// a diff image (differing pixels in red) are written to target/golden.
+//
+// After a deliberate visual change, run `UPDATE_GOLDEN=1 cargo test golden` to update the
+// references and review the new images like any other change.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::camera::Camera;
+use crate::force_field::{draw_force_overlay, Force, ForceField};
+use crate::hud::Hud;
+use crate::{draw_grid, Brush, CellType, Grid, View, BUFFER_WIDTH, GRID_SIZE, GRID_WIDTH};
+
+const SEED: u64 = 1;
+const DIFF_COLOR: [u8; 4] = [255, 0, 0, 255];
+
+fn reference_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden")
+}
+
+fn read_png(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return Err("reference image isn't 8-bit RGBA".into());
+    }
+    if info.width as usize != BUFFER_WIDTH || info.height as usize != BUFFER_WIDTH {
+        return Err(format!("reference image is {}x{}", info.width, info.height).into());
+    }
+    data.truncate(info.buffer_size());
+    Ok(data)
+}
+
+fn write_png(path: &Path, frame: &[u8]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let file = File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(BufWriter::new(file), BUFFER_WIDTH as u32, BUFFER_WIDTH as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(frame).unwrap();
+}
+
+fn assert_golden(name: &str, frame: &[u8]) {
+    let reference_path = reference_dir().join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_png(&reference_path, frame);
+        return;
+    }
+
+    let reference = read_png(&reference_path).unwrap_or_else(|err| {
+        panic!("couldn't read {}: {err}\nrun with UPDATE_GOLDEN=1 to create it", reference_path.display())
+    });
+    if reference == frame {
+        return;
+    }
+
+    // Differing pixels in red on top of a dimmed grayscale version of the actual frame
+    let mut diff = Vec::with_capacity(frame.len());
+    let mut differing = 0;
+    for (actual, expected) in frame.chunks_exact(4).zip(reference.chunks_exact(4)) {
+        if actual == expected {
+            let gray = ((actual[0] as u16 + actual[1] as u16 + actual[2] as u16) / 9) as u8;
+            diff.extend_from_slice(&[gray, gray, gray, 255]);
+        } else {
+            diff.extend_from_slice(&DIFF_COLOR);
+            differing += 1;
+        }
+    }
+
+    let actual_path = output_dir().join(format!("{name}.actual.png"));
+    let diff_path = output_dir().join(format!("{name}.diff.png"));
+    write_png(&actual_path, frame);
+    write_png(&diff_path, &diff);
+    panic!(
+        "{name} differs from the reference in {differing} pixels\nactual: {}\ndiff: {}",
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+fn triangle_wave(x: usize, period: usize, height: usize) -> usize {
+    (x % period).abs_diff(period / 2) * height * 2 / period
+}
+
+// Static landscape with every material, built without running any ticks
+fn landscape() -> Grid {
+    let mut grid = Grid::new(SEED);
+    for x in 0..GRID_WIDTH {
+        let ground = 140 + triangle_wave(x, 60, 16);
+        let dune = triangle_wave(x + 15, 40, 12);
+        for y in 0..GRID_WIDTH {
+            let cell_type = if y >= ground + 20 {
+                if (170..176).contains(&y) && (30..90).contains(&x) { &CellType::Coal } else { &CellType::Stone }
+            } else if y >= ground {
+                &CellType::Dirt
+            } else if (120..165).contains(&x) && y >= 138 {
+                &CellType::Water
+            } else if y >= ground - dune {
+                &CellType::Sand
+            } else if (60..70).contains(&x) && (40..50).contains(&y) {
+                &CellType::Co2
+            } else {
+                continue;
+            };
+            grid.place(y * GRID_WIDTH + x, cell_type);
+        }
+    }
+    grid
+}
+
+// Gives the movable cells made up state, so every debug view has something to show
+fn with_debug_state(mut grid: Grid) -> Grid {
+    for pos in 0..GRID_SIZE {
+        let (x, y) = (pos % GRID_WIDTH, pos / GRID_WIDTH);
+        grid.cells.velocity[pos] = (x as f32 / 40.0, y as f32 / 30.0 - 3.0);
+        grid.cells.free_falling[pos] = (x / 10 % 9) as u8;
+        grid.cells.grounded[pos] = (x / 20 + y / 20) % 2 == 0;
+        grid.cells.moved[pos] = (x + y) % 3 == 0;
+    }
+    grid
+}
+
+fn render(grid: &Grid, camera: &Camera, view: &View) -> Vec<u8> {
+    let mut frame = vec![0; BUFFER_WIDTH * BUFFER_WIDTH * 4];
+    draw_grid(&mut frame, grid, camera, view);
+    frame
+}
+
+#[test]
+fn golden_materials() {
+    assert_golden("materials", &render(&landscape(), &Camera::default(), &View::Normal));
+}
+
+#[test]
+fn golden_zoomed_camera() {
+    let mut camera = Camera::default();
+    camera.zoom_at((50.0, 150.0), 5.0);
+    assert_golden("zoomed_camera", &render(&landscape(), &camera, &View::Normal));
+}
+
+#[test]
+fn golden_debug_views() {
+    let grid = with_debug_state(landscape());
+    assert_golden("view_velocity", &render(&grid, &Camera::default(), &View::Velocity));
+    assert_golden("view_sleeping", &render(&grid, &Camera::default(), &View::FreeFalling));
+    assert_golden("view_grounded", &render(&grid, &Camera::default(), &View::Grounded));
+    assert_golden("view_moved", &render(&grid, &Camera::default(), &View::Moved));
+}
+
+#[test]
+fn golden_pressure_view() {
+    let mut grid = landscape();
+    grid.update_pressure();
+    assert_golden("view_pressure", &render(&grid, &Camera::default(), &View::Pressure));
+}
+
+#[test]
+fn golden_force_overlay() {
+    let mut grid = landscape();
+    grid.add_force_field(ForceField::new((40, 100), (48, 32), Force::Wind((0.2, -0.1))));
+    grid.add_force_field(ForceField::new((140, 60), (40, 40), Force::Vortex(0.3)));
+    let mut frame = render(&grid, &Camera::default(), &View::Normal);
+    draw_force_overlay(&mut frame, &grid, &Camera::default());
+    assert_golden("force_overlay", &frame);
+}
+
+#[test]
+fn golden_hud() {
+    let grid = landscape();
+    let mut frame = render(&grid, &Camera::default(), &View::Normal);
+    Hud::default().draw(&mut frame, BUFFER_WIDTH, &grid, &Brush::default(), &View::Velocity, None);
+    assert_golden("hud", &frame);
+}
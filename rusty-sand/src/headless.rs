@@ -0,0 +1,239 @@
+// Runs the simulation without opening a window, for batch experiments and machines without a GPU.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::boundary::{Boundaries, Boundary};
+use crate::scene::SceneError;
+use crate::scripting::{ScriptError, Scripts};
+use crate::terrain::Terrain;
+use crate::{CellType, Grid, GRAVITY, GRID_SIZE, GRID_WIDTH};
+
+pub const USAGE: &str = "\
+Usage: rusty-sand [--script <file>] [--terrain <mix>] [--puzzle <file>]
+       rusty-sand --headless [options]
+
+Options:
+    --scene <file>    Scene to start from, an empty grid if left out
+    --script <file>   Script defining custom materials
+    --terrain <mix>   Generate terrain from the seed instead of loading a scene, default or
+                      overrides like dirt=12,caves=0.5 (surface, hills, sand, dirt,
+                      bedrock, caves, coal, water)
+    --ticks <n>       Number of ticks to simulate (default 0)
+    --seed <n>        Seed for the simulation (default random)
+    --gravity <x,y>   Gravity in cells per tick squared (default 0,0.3)
+    --edges <modes>   Boundary mode for all edges, or for left,right,top,bottom
+                      (wall, void or wrap, default wall)
+    --png <file>      Write the final grid as a PNG image
+    --save <file>     Write the final grid as a scene file
+    --stats <file>    Write per-material statistics as JSON
+    --puzzle <file>   Puzzle to play, only in the window";
+
+pub struct Options {
+    scene: Option<PathBuf>,
+    script: Option<PathBuf>,
+    terrain: Option<Terrain>,
+    ticks: u64,
+    seed: u64,
+    gravity: (f32, f32),
+    boundaries: Boundaries,
+    png: Option<PathBuf>,
+    save: Option<PathBuf>,
+    stats: Option<PathBuf>
+}
+
+impl Options {
+    // Returns None when the arguments don't ask for headless mode
+    pub fn parse(args: &[String]) -> Result<Option<Options>, String> {
+        if !args.iter().any(|arg| arg == "--headless") {
+            return Ok(None);
+        }
+
+        let mut options = Options {
+            scene: None,
+            script: None,
+            terrain: None,
+            ticks: 0,
+            seed: rand::random(),
+            gravity: (0.0, GRAVITY),
+            boundaries: Boundaries::default(),
+            png: None,
+            save: None,
+            stats: None
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--headless" {
+                continue;
+            }
+            let value = args.next().ok_or(format!("missing value for {arg}"))?;
+            match arg.as_str() {
+                "--scene" => { options.scene = Some(value.into()); }
+                "--script" => { options.script = Some(value.into()); }
+                "--terrain" => { options.terrain = Some(Terrain::parse(value).ok_or(format!("invalid terrain '{value}'"))?); }
+                "--ticks" => { options.ticks = value.parse().map_err(|_| format!("invalid tick count '{value}'"))?; }
+                "--seed" => { options.seed = value.parse().map_err(|_| format!("invalid seed '{value}'"))?; }
+                "--gravity" => { options.gravity = parse_vector(value).ok_or(format!("invalid gravity '{value}'"))?; }
+                "--edges" => { options.boundaries = parse_boundaries(value).ok_or(format!("invalid edges '{value}'"))?; }
+                "--png" => { options.png = Some(value.into()); }
+                "--save" => { options.save = Some(value.into()); }
+                "--stats" => { options.stats = Some(value.into()); }
+                _ => { return Err(format!("unknown option {arg}")); }
+            }
+        }
+
+        if options.scene.is_some() && options.terrain.is_some() {
+            return Err("--scene and --terrain can't be used together".to_string());
+        }
+
+        Ok(Some(options))
+    }
+}
+
+fn parse_vector(value: &str) -> Option<(f32, f32)> {
+    let (x, y) = value.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+fn parse_boundaries(value: &str) -> Option<Boundaries> {
+    let modes: Option<Vec<Boundary>> = value.split(',').map(|name| Boundary::from_name(name.trim())).collect();
+    match modes?.as_slice() {
+        [boundary] => { Some(Boundaries::all(*boundary)) }
+        [left, right, top, bottom] => { Some(Boundaries { left: *left, right: *right, top: *top, bottom: *bottom }) }
+        _ => { None }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    Io(PathBuf, io::Error),
+    Scene(PathBuf, SceneError),
+    Script(PathBuf, ScriptError),
+    Png(PathBuf, png::EncodingError)
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::Io(path, _) => { write!(f, "couldn't access {}", path.display()) }
+            HeadlessError::Scene(path, _) => { write!(f, "couldn't load scene {}", path.display()) }
+            HeadlessError::Script(path, _) => { write!(f, "couldn't load script {}", path.display()) }
+            HeadlessError::Png(path, _) => { write!(f, "couldn't write image {}", path.display()) }
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HeadlessError::Io(_, err) => { Some(err) }
+            HeadlessError::Scene(_, err) => { Some(err) }
+            HeadlessError::Script(_, err) => { Some(err) }
+            HeadlessError::Png(_, err) => { Some(err) }
+        }
+    }
+}
+
+pub fn run(options: &Options) -> Result<(), HeadlessError> {
+    // Scripts go first, the scene may use the materials they define
+    let scripts = match &options.script {
+        Some(path) => { Some(Rc::new(Scripts::load_file(path).map_err(|err| HeadlessError::Script(path.clone(), err))?)) }
+        None => { None }
+    };
+
+    let mut grid = match &options.scene {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|err| HeadlessError::Io(path.clone(), err))?;
+            Grid::from_scene(&text, options.seed, scripts).map_err(|err| HeadlessError::Scene(path.clone(), err))?
+        }
+        None => {
+            let mut grid = match &options.terrain {
+                Some(terrain) => { Grid::from_terrain(options.seed, terrain) }
+                None => { Grid::new(options.seed) }
+            };
+            if let Some(scripts) = scripts {
+                grid.set_scripts(scripts);
+            }
+            grid
+        }
+    };
+    grid.gravity = options.gravity;
+    grid.boundaries = options.boundaries;
+
+    for _ in 0..options.ticks {
+        grid.execute_logic();
+    }
+
+    if let Some(path) = &options.png {
+        write_png(&grid, path)?;
+    }
+    if let Some(path) = &options.save {
+        fs::write(path, grid.to_scene()).map_err(|err| HeadlessError::Io(path.clone(), err))?;
+    }
+    if let Some(path) = &options.stats {
+        fs::write(path, stats_json(&grid, options)).map_err(|err| HeadlessError::Io(path.clone(), err))?;
+    }
+
+    Ok(())
+}
+
+fn grid_to_rgba(grid: &Grid) -> Vec<u8> {
+    (0..GRID_SIZE).flat_map(|pos| grid.cells.faded_color(pos, &grid.materials)).collect()
+}
+
+fn write_png(grid: &Grid, path: &PathBuf) -> Result<(), HeadlessError> {
+    let file = File::create(path).map_err(|err| HeadlessError::Io(path.clone(), err))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), GRID_WIDTH as u32, GRID_WIDTH as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|err| HeadlessError::Png(path.clone(), err))?;
+    writer.write_image_data(&grid_to_rgba(grid)).map_err(|err| HeadlessError::Png(path.clone(), err))
+}
+
+fn stats_json(grid: &Grid, options: &Options) -> String {
+    let counts = grid.count_materials();
+    let materials: Vec<String> = CellType::all(&grid.materials)
+        .into_iter()
+        .map(|cell_type| {
+            let count = counts[CellType::index(cell_type)];
+            format!(
+                "    {}: {{ \"count\": {}, \"fraction\": {} }}",
+                json_string(&CellType::get_name(cell_type, &grid.materials).to_lowercase()),
+                count,
+                count as f64 / GRID_SIZE as f64
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\n  \"seed\": {},\n  \"ticks\": {},\n  \"width\": {},\n  \"height\": {},\n  \"materials\": {{\n{}\n  }}\n}}\n",
+        options.seed,
+        options.ticks,
+        GRID_WIDTH,
+        GRID_WIDTH,
+        materials.join(",\n")
+    )
+}
+
+// Script materials can be named anything, so quotes, backslashes and control characters are escaped
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => { escaped.push_str("\\\""); }
+            '\\' => { escaped.push_str("\\\\"); }
+            '\n' => { escaped.push_str("\\n"); }
+            '\r' => { escaped.push_str("\\r"); }
+            '\t' => { escaped.push_str("\\t"); }
+            c if c.is_control() => { escaped.push_str(&format!("\\u{:04x}", c as u32)); }
+            c => { escaped.push(c); }
+        }
+    }
+    escaped.push('"');
+    escaped
+}
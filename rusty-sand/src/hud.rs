@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+use crate::boundary::Boundaries;
+use crate::font::{draw_text, fill_rect, shade_rect, text_width, GLYPH_HEIGHT, GLYPH_SPACING, GLYPH_WIDTH, LINE_HEIGHT};
+use crate::puzzle::Attempt;
+use crate::scripting::Materials;
+use crate::{Brush, CellType, Grid, View, GRAVITY};
+
+const HUD_MARGIN: usize = 2;
+const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+const FPS_SMOOTHING: f32 = 0.1;
+
+pub struct Hud {
+    pub visible: bool,
+    fps: f32,
+    tick_time: Duration,
+    last_frame: Option<Instant>
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Hud {
+            visible: true,
+            fps: 0.0,
+            tick_time: Duration::ZERO,
+            last_frame: None
+        }
+    }
+}
+
+impl Hud {
+    // Called once per rendered frame, keeps a smoothed frames-per-second value
+    pub fn record_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            let frame_time = now.duration_since(last_frame).as_secs_f32();
+            if frame_time > 0.0 {
+                let fps = 1.0 / frame_time;
+                if self.fps == 0.0 {
+                    self.fps = fps;
+                } else {
+                    self.fps += (fps - self.fps) * FPS_SMOOTHING;
+                }
+            }
+        }
+        self.last_frame = Some(now);
+    }
+
+    pub fn record_tick(&mut self, tick_time: Duration) {
+        self.tick_time = tick_time;
+    }
+
+    pub fn draw(&self, frame: &mut [u8], frame_width: usize, grid: &Grid, brush: &Brush, view: &View, puzzle: Option<&Attempt>) {
+        let counts = grid.count_materials();
+
+        let mut lines: Vec<(String, Option<[u8; 4]>)> = vec![
+            (format!("FPS {:.0}", self.fps), None),
+            (format!("TICK {:.2}MS", self.tick_time.as_secs_f32() * 1000.0), None),
+            (format!("BRUSH {} {}", CellType::get_name(brush.cell_type, &grid.materials), brush.size), None)
+        ];
+        if view.ne(&View::Normal) {
+            lines.append(&mut vec![(format!("VIEW {}", view.get_name()), None)]);
+        }
+        if grid.gravity != (0.0, GRAVITY) {
+            lines.append(&mut vec![(format!("GRAVITY {:.2} {:.2}", grid.gravity.0, grid.gravity.1), None)]);
+        }
+        if grid.boundaries != Boundaries::default() {
+            let edges = grid.boundaries;
+            lines.append(&mut vec![(format!("EDGES {} {} {} {}", edges.left.get_name(), edges.right.get_name(), edges.top.get_name(), edges.bottom.get_name()), None)]);
+        }
+        if let Some(attempt) = puzzle {
+            lines.append(&mut puzzle_lines(attempt, &grid.materials));
+        }
+        for cell_type in CellType::all(&grid.materials) {
+            let count = counts[CellType::index(cell_type)];
+            if cell_type.eq(&CellType::Air) || count == 0 {
+                continue;
+            }
+            let name = CellType::get_name(cell_type, &grid.materials);
+            lines.append(&mut vec![(format!("{} {}", name, count), Some(CellType::get_color(cell_type, &grid.materials)))]);
+        }
+
+        // Material lines start with a color swatch
+        let swatch_width = GLYPH_WIDTH + GLYPH_SPACING;
+        let mut width = 0;
+        for (text, swatch) in &lines {
+            let mut line_width = text_width(text);
+            if swatch.is_some() {
+                line_width += swatch_width;
+            }
+            width = width.max(line_width);
+        }
+        let height = lines.len() * LINE_HEIGHT - 1;
+
+        shade_rect(frame, frame_width, (0, 0), (width + HUD_MARGIN * 2, height + HUD_MARGIN * 2));
+
+        for (i, (text, swatch)) in lines.iter().enumerate() {
+            let mut x = HUD_MARGIN;
+            let y = HUD_MARGIN + i * LINE_HEIGHT;
+            if let Some(color) = swatch {
+                fill_rect(frame, frame_width, (x, y), (GLYPH_WIDTH, GLYPH_HEIGHT), *color);
+                x += swatch_width;
+            }
+            draw_text(frame, frame_width, (x, y), text, TEXT_COLOR);
+        }
+    }
+}
+
+// Title, the materials left to place and how far along the goals are
+fn puzzle_lines(attempt: &Attempt, materials: &Materials) -> Vec<(String, Option<[u8; 4]>)> {
+    let mut lines = vec![];
+    if !attempt.puzzle.title.is_empty() {
+        lines.append(&mut vec![(attempt.puzzle.title.to_uppercase(), None)]);
+    }
+    // Numbered by the key that selects them
+    for (i, (allowance, remaining)) in attempt.puzzle.allowed.iter().zip(&attempt.remaining).enumerate() {
+        let name = CellType::get_name(allowance.cell_type, materials);
+        let text = match remaining {
+            Some(remaining) => { format!("{} {} {} LEFT", i + 1, name, remaining) }
+            None => { format!("{} {}", i + 1, name) }
+        };
+        lines.append(&mut vec![(text, Some(CellType::get_color(allowance.cell_type, materials)))]);
+    }
+    for (goal, progress) in attempt.puzzle.goals.iter().zip(&attempt.progress) {
+        let name = CellType::get_name(goal.cell_type, materials);
+        lines.append(&mut vec![(format!("GOAL {} {}/{}", name, progress.min(&goal.count), goal.count), Some(CellType::get_color(goal.cell_type, materials)))]);
+    }
+    if let Some(ticks) = attempt.solved_at {
+        lines.append(&mut vec![(format!("SOLVED IN {} TICKS", ticks), None)]);
+    }
+    lines
+}
@@ -0,0 +1,47 @@
+// Cells of some materials only last for a while, like smoke or steam. Every cell counts its age in
+// ticks, and cells of a material with a lifetime turn into another material once they reach the
+// lifetime picked for them when they were created. Only the cells with a lifetime are visited.
+
+use crate::texture::scale_color;
+use crate::cells::Cells;
+use crate::scripting::Materials;
+use crate::{Cell, CellType, Grid, CELL_AIR};
+
+#[derive(Copy, Clone)]
+pub struct Lifetime {
+    pub ticks: (u16, u16), // Shortest and longest, picked at random for every cell
+    pub decays_into: &'static CellType,
+    pub fades: bool // Blends into the background with age
+}
+
+impl Cells {
+    // Color the cell is drawn with, older fading cells are darker
+    pub fn faded_color(&self, pos: usize, materials: &Materials) -> [u8;4] {
+        match (CellType::get_lifetime(self.material(pos), materials), self.lifetime(pos)) {
+            (Some(material), Some(lifetime)) if material.fades => {
+                scale_color(self.color(pos), 1.0 - self.age(pos) as f32 / lifetime.max(1) as f32)
+            }
+            _ => { self.color(pos) }
+        }
+    }
+}
+
+impl Grid {
+    pub fn decay(&mut self) {
+        self.cells.advance_tick();
+        // Backwards, a cell that stops having a lifetime is replaced in the list by the last one,
+        // which has already been checked
+        for slot in (0..self.cells.timed().len()).rev() {
+            let pos = self.cells.timed()[slot];
+            if self.cells.lifetime(pos).is_none_or(|lifetime| self.cells.age(pos) < lifetime) {
+                continue;
+            }
+
+            // Keeps the temperature like any other change of material
+            let decays_into = CellType::get_lifetime(self.cells.material(pos), &self.materials).map_or(&CELL_AIR, |material| material.decays_into);
+            let temperature = self.cells.temperature[pos];
+            self.cells.set(pos, Cell::new_at(decays_into, pos, &self.materials, &mut self.rng));
+            self.cells.temperature[pos] = temperature;
+        }
+    }
+}
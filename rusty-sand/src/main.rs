@@ -1,81 +1,309 @@
+mod boundary;
+mod camera;
+mod cells;
+mod explosion;
+mod fluid;
+mod font;
+mod force_field;
+#[cfg(test)]
+mod golden_tests;
+mod headless;
+mod hud;
+mod lifetime;
+mod puzzle;
+mod reaction;
+mod rigid_body;
+mod scene;
+mod scripting;
+#[cfg(test)]
+mod sim_tests;
+mod terrain;
+mod texture;
+
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use error_iter::ErrorIter;
 use log::error;
 use pixels::{Pixels, SurfaceTexture};
-use rand::{random, Rng};
-use rand::prelude::ThreadRng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use winit::application::ApplicationHandler;
-use winit::dpi::{LogicalSize, PhysicalPosition};
-use winit::event::{WindowEvent, DeviceEvent, DeviceId, MouseButton, ElementState};
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
+use winit::event::{WindowEvent, DeviceEvent, DeviceId, MouseButton, MouseScrollDelta, ElementState};
 use winit::event_loop::{EventLoop, ActiveEventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{Window, WindowId};
+use crate::boundary::{Boundaries, Boundary, Crossing};
+use crate::camera::Camera;
+use crate::cells::Cells;
+use crate::force_field::{draw_force_overlay, Force, ForceField};
+use crate::hud::Hud;
+use crate::lifetime::Lifetime;
+use crate::puzzle::{draw_puzzle_overlay, Attempt, Puzzle};
+use crate::rigid_body::Body;
+use crate::scripting::{Materials, Scripts};
+use crate::terrain::Terrain;
+use crate::texture::{offset_color, Texture};
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 800;
 const GRID_WIDTH: usize = 200;
 const GRID_SIZE: usize = GRID_WIDTH * GRID_WIDTH;
+const BUFFER_WIDTH: usize = 200;
+const PIXELS_PER_SCROLL_LINE: f32 = 50.0;
+const TICK_RATE: u32 = 60;
+const MAX_TICKS_PER_FRAME: u32 = 5;
+const MAX_BRUSH_SIZE: usize = 16;
+const FREE_FALLING_THRESHOLD: u8 = 4;
+const HEATMAP_MAX_VELOCITY: f32 = 10.0;
+const HEATMAP_MAX_PRESSURE: f32 = 40.0;
+const AMBIENT_TEMPERATURE: f32 = 20.0;
+const GRAVITY: f32 = 0.3;
+const IGNITION_TEMPERATURE: f32 = 300.0;
+const MIN_BOUNCE_SPEED: f32 = 1.0;
+const LIQUID_DRAG: f32 = 0.3;
+const EXPLOSION_RADIUS: f32 = 10.0;
+const EXPLOSION_STRENGTH: f32 = 6.0;
+const FORCE_FIELD_SIZE: usize = 32;
 
 const CELL_AIR: CellType = CellType::Air;
 const CELL_SAND: CellType = CellType::Sand;
 const CELL_DIRT: CellType = CellType::Dirt;
+const CELL_STONE: CellType = CellType::Stone;
+const CELL_EXPLOSIVE: CellType = CellType::Explosive;
 
 #[derive(Default)]
 struct World {
     pixels: Option<Pixels>,
-    grid: Grid
+    grid: Grid,
+    view: View,
+    camera: Camera,
+    show_force_fields: bool,
+    puzzle: Option<Attempt> // Puzzle being played, None in the free sandbox
 }
 
 impl World {
     fn draw(&mut self) {
         let frame = self.pixels.as_mut().unwrap().frame_mut();
-        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let cell = self.grid.grid[i];
-            let rgba:&[u8;4] = &cell.color;
+        draw_grid(frame, &self.grid, &self.camera, &self.view);
+        if self.show_force_fields {
+            draw_force_overlay(frame, &self.grid, &self.camera);
+        }
+        if let Some(attempt) = &self.puzzle {
+            draw_puzzle_overlay(frame, attempt, &self.camera);
+        }
+    }
+
+    fn draw_hud(&mut self, hud: &Hud, brush: &Brush) {
+        let frame = self.pixels.as_mut().unwrap().frame_mut();
+        hud.draw(frame, BUFFER_WIDTH, &self.grid, brush, &self.view, self.puzzle.as_ref());
+    }
 
-            pixel.copy_from_slice(rgba);
+    // Switches to the given debug view, or back to the normal one if it is already active
+    fn toggle_view(&mut self, view: View) {
+        if self.view == view {
+            self.view = View::Normal;
+        } else {
+            self.view = view;
+        }
+    }
+}
+
+// Draws the grid as seen through the camera into a BUFFER_WIDTH wide RGBA frame
+fn draw_grid(frame: &mut [u8], grid: &Grid, camera: &Camera, view: &View) {
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        let (x, y) = camera.to_grid((i % BUFFER_WIDTH, i / BUFFER_WIDTH));
+        let pos = y * GRID_WIDTH + x;
+        let rgba:&[u8;4] = &view.get_color(&grid.cells, &grid.materials, pos);
+
+        pixel.copy_from_slice(rgba);
+    }
+}
+
+// Render modes visualizing the internal state of movable solids
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+enum View {
+    #[default]
+    Normal,
+    Velocity,
+    FreeFalling,
+    Grounded,
+    Moved,
+    Pressure
+}
+
+impl View {
+    fn get_name(&self) -> &'static str {
+        match self {
+            View::Normal => { "NORMAL" }
+            View::Velocity => { "VELOCITY" }
+            View::FreeFalling => { "SLEEPING" }
+            View::Grounded => { "GROUNDED" }
+            View::Moved => { "MOVED" }
+            View::Pressure => { "PRESSURE" }
+        }
+    }
+
+    fn get_color(&self, cells: &Cells, materials: &Materials, pos: usize) -> [u8;4] {
+        if self.eq(&View::Normal) || cells.is_air(pos) {
+            return cells.faded_color(pos, materials);
+        }
+        // Cells without movable solid logic carry no state worth showing, only fluids have pressure
+        let cell_type = cells.material(pos);
+        let has_state = if self.eq(&View::Pressure) {
+            CellType::is_liquid(cell_type, materials) || CellType::is_gas(cell_type, materials)
+        } else {
+            CellType::is_movable_solid(cell_type, materials)
+        };
+        if !has_state {
+            return [40, 40, 40, 255];
+        }
+
+        match self {
+            View::Normal => { cells.faded_color(pos, materials) }
+            View::Velocity => {
+                let velocity = cells.velocity[pos];
+                let speed = (velocity.0 * velocity.0 + velocity.1 * velocity.1).sqrt();
+                heatmap(speed / HEATMAP_MAX_VELOCITY)
+            }
+            View::FreeFalling => {
+                let free_falling = cells.free_falling[pos];
+                if free_falling == FREE_FALLING_THRESHOLD * 2 {
+                    [255, 0, 255, 255] // Woken up by a neighbour this tick
+                } else if free_falling >= FREE_FALLING_THRESHOLD {
+                    [30, 60, 200, 255] // Sleeping
+                } else {
+                    [230, 60, 30, 255] // Awake
+                }
+            }
+            View::Grounded => {
+                if cells.grounded[pos] {
+                    [40, 200, 60, 255]
+                } else {
+                    [230, 60, 30, 255]
+                }
+            }
+            View::Moved => {
+                if cells.moved[pos] {
+                    [255, 255, 255, 255]
+                } else {
+                    [70, 70, 70, 255]
+                }
+            }
+            View::Pressure => { heatmap(cells.pressure[pos] / HEATMAP_MAX_PRESSURE) }
         }
     }
 }
 
 struct Grid {
-    grid: Vec<Cell>
+    cells: Cells,
+    rng: StdRng,
+    scripts: Option<Rc<Scripts>>,
+    materials: Rc<Materials>, // Defined by the scripts, shared with them
+    bodies: Vec<Body>,
+    force_fields: Vec<ForceField>,
+    gravity: (f32, f32), // Acceleration of everything that falls, in cells per tick squared
+    boundaries: Boundaries
 }
 
 impl Default for Grid {
     fn default() -> Self {
-        Grid {
-            grid: vec![Cell::new(&CELL_AIR); GRID_SIZE]
-        }
+        Grid::new(rand::random())
     }
 }
 
 impl Grid {
+    // The seed drives every random decision, so the same seed and input give the same simulation
+    fn new(seed: u64) -> Grid {
+        Grid {
+            cells: Cells::filled(Cell::new(&CELL_AIR, &Materials::default()), GRID_SIZE),
+            rng: StdRng::seed_from_u64(seed),
+            scripts: None,
+            materials: Rc::default(),
+            bodies: vec![],
+            force_fields: vec![],
+            gravity: (0.0, GRAVITY),
+            boundaries: Boundaries::default()
+        }
+    }
+
+    fn set_scripts(&mut self, scripts: Rc<Scripts>) {
+        self.materials = scripts.materials.clone();
+        self.scripts = Some(scripts);
+    }
+
     fn place(&mut self, pos: usize, cell_type: &'static CellType) {
-        let cell = &self.grid[pos];
-        if cell.cell_type.eq(&CELL_AIR) {
-            self.grid[pos] = Cell::new(cell_type);
+        if self.cells.is_air(pos) {
+            self.cells.set(pos, Cell::new_at(cell_type, pos, &self.materials, &mut self.rng));
+        }
+    }
+
+    fn place_circle(&mut self, center: (i32, i32), size: usize, cell_type: &'static CellType) {
+        for pos in circle_cells(center, size) {
+            self.place(pos, cell_type);
+        }
+    }
+
+    fn place_line(&mut self, pos1: (usize, usize), pos2: (usize, usize), size: usize, cell_type: &'static CellType) {
+        for point in generate_line((pos1.0 as i32, pos1.1 as i32), (pos2.0 as i32, pos2.1 as i32)){
+            self.place_circle(point, size, cell_type);
         }
     }
 
-    fn place_line(&mut self, pos1: (usize, usize), pos2: (usize, usize), cell_type: &'static CellType) {
-        for point in generate_line(pos1, pos2){
-            self.place((point.1 as usize) * GRID_WIDTH + (point.0 as usize), cell_type);
+    // Counts indexed by CellType::index
+    fn count_materials(&self) -> Vec<usize> {
+        let mut counts = vec![0; CellType::all(&self.materials).len()];
+        for index in self.cells.material_indices() {
+            counts[index] += 1;
         }
+        counts
     }
 
     fn execute_logic(&mut self) {
+        self.step_bodies();
+        self.apply_force_fields();
+
         let mut changes = Changes::default();
+        // Cell logic reads the whole grid, so it works on a copy of the generator that is stored back afterwards
+        let mut rng = self.rng.clone();
 
         for i in 0..GRID_SIZE {
-            let mut cell: Cell = self.grid[i];
-            cell.logic(self, i, &mut changes);
-            self.grid[i] = cell;
+            // Most of the grid is air or walls, skipped without looking any further
+            if CellType::is_inert(self.cells.material(i)) {
+                continue;
+            }
+            Cell::logic(self, i, &mut changes, &mut rng);
         }
+        self.rng = rng;
 
+        // Before the swaps, the cells leaving the grid and the ones that were hit are still where they were
+        for pos in changes.removed {
+            self.cells.set(pos, Cell::new(&CELL_AIR, &self.materials));
+        }
+        for (pos, impulse) in changes.impulses {
+            let velocity = &mut self.cells.velocity[pos];
+            *velocity = (velocity.0 + impulse.0, velocity.1 + impulse.1);
+            self.cells.free_falling[pos] = 0;
+        }
         for pos in changes.pos {
-            self.grid.swap(pos.0, pos.1);
+            self.cells.swap(pos.0, pos.1);
         }
         for free_falling in changes.free_falling {
-            self.grid[free_falling.0].free_falling = free_falling.1;
+            self.cells.free_falling[free_falling.0] = free_falling.1;
+        }
+        // Only into cells that are still empty after the moves
+        for (pos, cell_type) in changes.spawned {
+            self.place(pos, cell_type);
+        }
+        self.update_pressure();
+        self.apply_reactions();
+        self.decay();
+        for pos in changes.explosions {
+            // May already be gone in the explosion of a neighbour
+            if self.cells.material(pos).eq(&CELL_EXPLOSIVE) {
+                self.explode(((pos % GRID_WIDTH) as f32, (pos / GRID_WIDTH) as f32), EXPLOSION_RADIUS, EXPLOSION_STRENGTH);
+            }
         }
     }
 }
@@ -83,175 +311,281 @@ impl Grid {
 #[derive(Default)]
 struct Changes {
     pos: Vec<(usize, usize)>,
-    free_falling: Vec<(usize, u8)>
+    free_falling: Vec<(usize, u8)>,
+    explosions: Vec<usize>,
+    removed: Vec<usize>,
+    spawned: Vec<(usize, &'static CellType)>,
+    impulses: Vec<(usize, (f32, f32))> // Velocity added to cells that were hit
 }
 
 #[derive(Copy, Clone)]
 struct Cell {
     cell_type: &'static CellType,
     velocity: (f32, f32),
+    remainder: (f32, f32), // Distance travelled since the last whole cell, in cells
     free_falling: u8,
-    pos: usize,
+    moved: bool, // Changed place since its logic last ran
     grounded: bool,
-    color: [u8;4]
+    color: [u8;4],
+    temperature: f32,
+    pressure: f32, // Depth below the surface for liquids, how packed it is for gases
+    age: u16, // Ticks since the cell was created
+    lifetime: Option<u16> // Age at which it decays, for materials that do
 }
 
 impl Cell {
-    fn new(cell_type: &'static CellType) -> Cell {
-        let mut color = [0u8;4];
-        match cell_type {
-            CellType::Sand => {
-                color = [252, 186, 3, 255];
-            }
-            CellType::Dirt => {
-                color = [89, 44, 20, 255];
-            }
-            _ => {
-                color = [0,0,0, 255];
-            }
-        }
+    fn new(cell_type: &'static CellType, materials: &Materials) -> Cell {
         Cell {
             cell_type,
             velocity: (0.0,0.0),
+            remainder: (0.0, 0.0),
             free_falling: 0,
-            pos: GRID_SIZE + 1,
+            moved: true,
             grounded: false,
-            color
+            color: CellType::get_color(cell_type, materials),
+            temperature: CellType::get_initial_temperature(cell_type, materials),
+            pressure: 0.0,
+            age: 0,
+            lifetime: CellType::get_lifetime(cell_type, materials).map(|lifetime| lifetime.ticks.1)
         }
     }
 
-    fn logic(&mut self, grid: &Grid, pos: usize, changes: &mut Changes) {
-        match self.cell_type {
-            CellType::Sand => {
-                self.movable_solid_logic(grid, pos, changes); // Calculate all the forces and set them to self.velocity
-
-                let mut new_pos = pos;
-                if self.velocity.0 != 0.0 || self.free_falling < 4 {
-                    new_pos = self.physics(grid, pos); // Calculate physics based on the self.velocity
-                }
+    // Creates a cell with a palette color picked for it and the material's texture applied for the given position
+    fn new_at(cell_type: &'static CellType, pos: usize, materials: &Materials, rng: &mut StdRng) -> Cell {
+        let mut cell = Cell::new(cell_type, materials);
+        let pos_xy = (pos % GRID_WIDTH, pos / GRID_WIDTH);
+        cell.color = CellType::get_texture(cell_type).apply(CellType::pick_color(cell_type, materials, rng), pos_xy);
+        cell.lifetime = CellType::get_lifetime(cell_type, materials).map(|lifetime| rng.random_range(lifetime.ticks.0..=lifetime.ticks.1));
+        cell
+    }
 
-                if pos != new_pos {
-                    changes.pos.append(&mut vec![(pos, new_pos)]);
-                }
-                self.pos = pos;
+    // Works on the cell's state in the grid's arrays, so only the properties it needs are touched
+    fn logic(grid: &mut Grid, pos: usize, changes: &mut Changes, rng: &mut StdRng) {
+        match grid.cells.material(pos) {
+            CellType::Sand => {
+                Self::movable_solid_step(grid, pos, changes, rng);
             }
             CellType::Dirt => {
-                self.movable_solid_logic(grid, pos, changes); // Calculate all the forces and set them to self.velocity
-
-                let mut new_pos = pos;
-                if self.velocity.0 != 0.0 || self.free_falling < 4 {
-                    new_pos = self.physics(grid, pos); // Calculate physics based on the self.velocity
+                Self::movable_solid_step(grid, pos, changes, rng);
+            }
+            CellType::Debris => {
+                Self::movable_solid_step(grid, pos, changes, rng);
+            }
+            CellType::Explosive => {
+                let hot = |temperature: f32| temperature >= IGNITION_TEMPERATURE;
+                if hot(grid.cells.temperature[pos]) || Self::get_neighbours(grid, pos).iter().any(|(p, n)| n.is_some() && hot(grid.cells.temperature[*p])) {
+                    changes.explosions.append(&mut vec![pos]);
                 }
-
-                if pos != new_pos {
-                    changes.pos.append(&mut vec![(pos, new_pos)]);
+            }
+            CellType::Water => {
+                Self::liquid_step(grid, pos, changes, rng);
+            }
+            CellType::Co2 | CellType::Smoke => {
+                Self::gas_step(grid, pos, changes, rng);
+            }
+            CellType::Spout => {
+                Self::emit(grid, pos, changes, rng);
+            }
+            CellType::Drain => {
+                Self::drain(grid, pos, changes);
+            }
+            CellType::Script(id) => {
+                // Shared, the tick function may change the cell while its material is still needed
+                let materials = grid.materials.clone();
+                let material = materials.get(*id);
+                if material.movable {
+                    Self::movable_solid_step(grid, pos, changes, rng);
+                } else if material.liquid {
+                    Self::liquid_step(grid, pos, changes, rng);
+                } else if material.gas {
+                    Self::gas_step(grid, pos, changes, rng);
+                }
+                Self::emit(grid, pos, changes, rng);
+                if CellType::is_drain(grid.cells.material(pos), &materials) {
+                    Self::drain(grid, pos, changes);
+                }
+                if let (Some(scripts), Some(tick)) = (grid.scripts.clone(), &material.tick) {
+                    scripts.tick(tick, grid, pos, changes, rng);
                 }
-                self.pos = pos;
+                grid.cells.moved[pos] = false;
             }
             _ => {}
         }
     }
 
-    fn movable_solid_logic(&mut self, grid: &Grid, pos: usize, changes: &mut Changes) {
-        let mut rng = rand::thread_rng();
-        let free_falling_threshold = 4u8;
-        let mut neighbours: [(usize, Option<&Cell>);8] = [(0, None); 8];
+    // Puts a new cell of the emitted material into a random neighbouring air cell
+    fn emit(grid: &Grid, pos: usize, changes: &mut Changes, rng: &mut StdRng) {
+        let Some((material, rate)) = CellType::get_emission(grid.cells.material(pos), &grid.materials) else {
+            return;
+        };
+        if !rng.random_bool(rate) {
+            return;
+        }
+        let free: Vec<usize> = Self::get_neighbours(grid, pos)
+            .iter()
+            .filter(|(_, n)| n.is_some_and(|n| n.eq(&CELL_AIR)))
+            .map(|(p, _)| *p)
+            .collect();
+        if !free.is_empty() {
+            changes.spawned.append(&mut vec![(free[rng.random_range(0..free.len())], material)]);
+        }
+    }
+
+    // Deletes the loose cells next to it, walls and other fixed cells stay
+    fn drain(grid: &Grid, pos: usize, changes: &mut Changes) {
+        for (p, n) in Self::get_neighbours(grid, pos) {
+            let loose = |n: &CellType| !n.eq(&CELL_AIR) && (CellType::is_movable_solid(n, &grid.materials) || !CellType::is_solid(n, &grid.materials));
+            if n.is_some_and(loose) {
+                changes.removed.append(&mut vec![p]);
+            }
+        }
+    }
+
+    fn movable_solid_step(grid: &mut Grid, pos: usize, changes: &mut Changes, rng: &mut StdRng) {
+        Self::movable_solid_logic(grid, pos, changes, rng); // Calculate all the forces and set them to the cell's velocity
+
+        // Resting cells only keep pressing down, there is nothing to move
+        let moving_sideways = GravityFrame::new(grid.gravity).is_none_or(|frame| frame.to_local(grid.cells.velocity[pos]).0 != 0.0);
+        let mut new_pos = pos;
+        if moving_sideways || grid.cells.free_falling[pos] < 4 {
+            // Calculate physics based on the cell's velocity
+            let Some(physics_pos) = Self::physics(grid, pos, changes) else {
+                changes.removed.append(&mut vec![pos]); // Fell out through a void edge
+                return;
+            };
+            new_pos = physics_pos;
+        }
+
+        if pos != new_pos {
+            changes.pos.append(&mut vec![(pos, new_pos)]);
+        }
+        grid.cells.moved[pos] = false;
+    }
+
+    fn movable_solid_logic(grid: &mut Grid, pos: usize, changes: &mut Changes, rng: &mut StdRng) {
+        // Without gravity nothing rests on anything, cells keep drifting until they hit something
+        let Some(frame) = GravityFrame::new(grid.gravity) else {
+            grid.cells.free_falling[pos] = 0;
+            grid.cells.grounded[pos] = false;
+            return;
+        };
+        let cell_type = grid.cells.material(pos);
+        let mut free_falling = grid.cells.free_falling[pos];
+        let was_grounded = grid.cells.grounded[pos];
+        let below = Self::neighbour_index(frame.below);
+        let below_left = Self::neighbour_index(frame.below_left);
+        let below_right = Self::neighbour_index(frame.below_right);
+        let left = Self::neighbour_index(frame.left);
+        let right = Self::neighbour_index(frame.right);
+
+        let mut neighbours: [(usize, Option<&'static CellType>);8] = [(0, None); 8];
 
         // Set the free-falling flag of neighbour cells
-        if self.free_falling < free_falling_threshold {
+        if free_falling < FREE_FALLING_THRESHOLD {
             neighbours = Self::get_neighbours(grid, pos);
             let mut movable_solid_neighbours = vec![];
             for (p, n) in neighbours {
-                if n.is_some() {
-                    if CellType::is_movable_solid(n.unwrap().cell_type) {
-                        movable_solid_neighbours.append(&mut vec![p])
-                    }
+                if n.is_some_and(|n| CellType::is_movable_solid(n, &grid.materials)) {
+                    movable_solid_neighbours.append(&mut vec![p])
                 }
             }
 
             if movable_solid_neighbours.len() < 5 {
                 for n in movable_solid_neighbours {
-                    if rng.gen_bool(1.0 - CellType::get_inertial_resistance(grid.grid[n].cell_type)) {
-                        changes.free_falling.append(&mut vec![(n, free_falling_threshold * 2)]);
+                    if rng.random_bool(1.0 - CellType::get_inertial_resistance(grid.cells.material(n), &grid.materials)) {
+                        changes.free_falling.append(&mut vec![(n, FREE_FALLING_THRESHOLD * 2)]);
                     }
                 }
             }
         }
-        else {
-            if self.free_falling == free_falling_threshold * 2 {
-                for i in 0..3 {
-                    neighbours[5 + i] = Self::get_neighbour(grid, pos, (-1 + i as i8, 1))
-                }
-            }
-            else {
-                neighbours[6] = Self::get_neighbour(grid, pos, (0, 1));
+        else if free_falling == FREE_FALLING_THRESHOLD * 2 {
+            for dir in [frame.below_left, frame.below, frame.below_right] {
+                neighbours[Self::neighbour_index(dir)] = Self::get_neighbour(grid, pos, dir);
             }
         }
+        else {
+            neighbours[below] = Self::get_neighbour(grid, pos, frame.below);
+        }
 
 
         // Validate the change of free-falling flag by external cell
-        if self.free_falling == free_falling_threshold * 2 {
+        if free_falling == FREE_FALLING_THRESHOLD * 2 {
             let mut occupied_count: u8 = 0;
-            for i in 0..3 {
-                if neighbours[5 + i].1.is_some() {
-                    if CellType::is_solid(neighbours[5 + i].1.unwrap().cell_type) {
+            for i in [below_left, below, below_right] {
+                match neighbours[i].1 {
+                    Some(n) => {
+                        if CellType::is_solid(n, &grid.materials) {
+                            occupied_count += 1;
+                        }
+                    }
+                    None => {
                         occupied_count += 1;
                     }
                 }
-                else {
-                    occupied_count += 1;
-                }
             }
 
             if occupied_count == 3 {
-                self.free_falling = free_falling_threshold;
+                free_falling = FREE_FALLING_THRESHOLD;
             }
             else {
-                self.free_falling = 0;
+                free_falling = 0;
             }
         }
 
+        // Sideways and falling parts of the velocity
+        let mut velocity = frame.to_local(grid.cells.velocity[pos]);
 
-        // Has solid under
+        // Has solid under, liquids and gases don't hold the cell up and neither does anything past a void edge
         let mut grounded = true;
-        if neighbours[6].1.is_some() {
-            if neighbours[6].1.unwrap().cell_type.eq(&CELL_AIR) {
-                grounded = false;
-            }
+        if neighbours[below].1.is_some_and(|n| !CellType::is_solid(n, &grid.materials)) || grid.boundaries.leads_into_void(pos, frame.below) {
+            grounded = false;
+        }
+        // Flung upwards, it will come back down
+        if velocity.1 < 0.0 {
+            grounded = false;
         }
 
-        // Horizontal velocity drag
-        if self.velocity.0.abs() >= 1.0 {
-            self.velocity.0 *= 0.8;
-            if self.velocity.0.abs() <= 1.0 {
-                self.velocity.0 = 0.0;
+        // Sinking through a liquid slows the cell down a lot more than falling through the air
+        let mut drag = CellType::get_drag(cell_type, &grid.materials);
+        if neighbours[below].1.is_some_and(|n| CellType::is_liquid(n, &grid.materials)) {
+            drag = ((drag.0 + LIQUID_DRAG).min(1.0), (drag.1 + LIQUID_DRAG).min(1.0));
+        }
+
+        // Sideways drag. Only cells on the ground stop, in the air wind may be building it up.
+        velocity.0 *= 1.0 - drag.0;
+        if velocity.0.abs() <= 1.0 && grounded {
+            velocity.0 = 0.0;
+        }
+
+        // Landing hard enough on something hard enough bounces the cell back up
+        if grounded && !was_grounded {
+            let rebound = velocity.1 * Self::restitution(cell_type, neighbours[below].1.unwrap_or(&CELL_STONE), &grid.materials);
+            if rebound >= MIN_BOUNCE_SPEED {
+                velocity.1 = -rebound;
+                grounded = false;
             }
         }
 
         if !grounded {
-            self.velocity.1 += 0.3; // Gravity
-            self.free_falling = 0;
+            velocity.1 = ((velocity.1 + frame.strength) * (1.0 - drag.1)).min(CellType::get_terminal_velocity(cell_type, &grid.materials));
+            free_falling = 0;
         }
         else {
-            if !self.grounded { // If grounded from previous frame was false - did it just hit the ground
-                let r:f64 = rng.gen();
-                let absorbed_speed = 4.0_f32.min(self.velocity.1 * (r as f32));
+            if !was_grounded { // If grounded from previous frame was false - did it just hit the ground
+                let r:f64 = rng.random();
+                let absorbed_speed = 4.0_f32.min(velocity.1 * (r as f32));
 
                 let mut left_free = false;
-                if neighbours[3].1.is_some() && self.velocity.0 <= 0.0 {
-                    if neighbours[3].1.unwrap().cell_type.eq(&CELL_AIR) {
-                        left_free = true;
-                    }
+                if velocity.0 <= 0.0 && neighbours[left].1.is_some_and(|n| n.eq(&CELL_AIR)) {
+                    left_free = true;
                 }
                 let mut right_free = false;
-                if neighbours[4].1.is_some() && self.velocity.0 >= 0.0 {
-                    if neighbours[4].1.unwrap().cell_type.eq(&CELL_AIR) {
-                        right_free = true;
-                    }
+                if velocity.0 >= 0.0 && neighbours[right].1.is_some_and(|n| n.eq(&CELL_AIR)) {
+                    right_free = true;
                 }
 
                 if left_free && right_free {
-                    if rng.gen_bool(0.5) {
+                    if rng.random_bool(0.5) {
                         left_free = false;
                     } else {
                         right_free = false;
@@ -259,93 +593,113 @@ impl Cell {
                 }
 
                 if left_free {
-                    self.velocity.0 = -absorbed_speed;
+                    velocity.0 = -absorbed_speed;
                 } else if right_free {
-                    self.velocity.0 = absorbed_speed;
+                    velocity.0 = absorbed_speed;
                 }
             }
 
-            else if self.free_falling < free_falling_threshold { // Is in free-fall state
+            else if free_falling < FREE_FALLING_THRESHOLD { // Is in free-fall state
                 let mut left_bottom_free = false;
-                if neighbours[5].1.is_some() && self.velocity.0 <= 0.0 {
-                    if neighbours[5].1.unwrap().cell_type.eq(&CELL_AIR) {
-                        left_bottom_free = true;
-                    }
+                if velocity.0 <= 0.0 && neighbours[below_left].1.is_some_and(|n| n.eq(&CELL_AIR)) {
+                    left_bottom_free = true;
                 }
                 let mut right_bottom_free = false;
-                if neighbours[7].1.is_some() && self.velocity.0 >= 0.0 {
-                    if neighbours[7].1.unwrap().cell_type.eq(&CELL_AIR) {
-                        right_bottom_free = true;
-                    }
+                if velocity.0 >= 0.0 && neighbours[below_right].1.is_some_and(|n| n.eq(&CELL_AIR)) {
+                    right_bottom_free = true;
                 }
 
                 if left_bottom_free && right_bottom_free {
-                    if rng.gen_bool(0.5) {
+                    if rng.random_bool(0.5) {
                         left_bottom_free = false;
                     } else {
                         right_bottom_free = false;
                     }
                 }
-                else if rng.gen_bool(CellType::get_inertial_resistance(&CELL_SAND).powf(3.0)) { // There is a chance for the cell to stop moving at the edge of the hill
-                    self.free_falling = free_falling_threshold;
+                else if rng.random_bool(CellType::get_inertial_resistance(&CELL_SAND, &grid.materials).powf(3.0)) { // There is a chance for the cell to stop moving at the edge of the hill
+                    free_falling = FREE_FALLING_THRESHOLD;
                     left_bottom_free = false;
                     right_bottom_free = false;
                 }
 
                 if left_bottom_free {
-                    self.velocity.0 = -CellType::get_roll_speed(self.cell_type);
+                    velocity.0 = -CellType::get_roll_speed(cell_type, &grid.materials);
                 } else if right_bottom_free {
-                    self.velocity.0 = CellType::get_roll_speed(self.cell_type);
+                    velocity.0 = CellType::get_roll_speed(cell_type, &grid.materials);
                 }
             }
 
-            self.velocity.1 = CellType::get_roll_speed(self.cell_type); // Constant weight kinda
-            if self.pos == pos { // If the pos didn't change from the previous frame
-                self.free_falling += 1;
-                if self.free_falling > free_falling_threshold {
-                    self.free_falling = free_falling_threshold;
-                    self.velocity.0 = 0.0;
+            velocity.1 = CellType::get_roll_speed(cell_type, &grid.materials); // Constant weight kinda
+            if !grid.cells.moved[pos] { // If the pos didn't change from the previous frame
+                free_falling += 1;
+                if free_falling > FREE_FALLING_THRESHOLD {
+                    free_falling = FREE_FALLING_THRESHOLD;
+                    velocity.0 = 0.0;
                 }
             }
         }
 
-        self.grounded = grounded;
+        grid.cells.velocity[pos] = frame.to_world(velocity);
+        grid.cells.grounded[pos] = grounded;
+        grid.cells.free_falling[pos] = free_falling;
     }
 
-    fn physics(&mut self, grid: &Grid, pos: usize) -> usize {
+    // None when the cell leaves the grid through a void edge
+    fn physics(grid: &mut Grid, pos: usize, changes: &mut Changes) -> Option<usize> {
         let mut new_pos = pos;
-        let pos_xy = (pos % GRID_WIDTH, pos / GRID_WIDTH);
-        let mut intended_pos_xy = (((pos % GRID_WIDTH) as i32) + self.velocity.0 as i32, ((pos / GRID_WIDTH) as i32) + self.velocity.1 as i32);
-        if intended_pos_xy.0 < 0 {
+        let pos_xy = ((pos % GRID_WIDTH) as i32, (pos / GRID_WIDTH) as i32);
+        // Only whole cells are moved, the fraction left over carries on to the next tick so slow cells still get somewhere
+        let (velocity, remainder) = (grid.cells.velocity[pos], grid.cells.remainder[pos]);
+        let travel = (velocity.0 + remainder.0, velocity.1 + remainder.1);
+        grid.cells.remainder[pos] = (travel.0.fract(), travel.1.fract());
+        let unclamped_pos_xy = (pos_xy.0 + travel.0 as i32, pos_xy.1 + travel.1 as i32);
+        let mut intended_pos_xy = unclamped_pos_xy;
+        // Walls stop the cell at the edge, it only goes past the other edges
+        let boundaries = grid.boundaries;
+        if intended_pos_xy.0 < 0 && boundaries.left == Boundary::Wall {
             intended_pos_xy.0 = 0;
         }
-        if intended_pos_xy.1 < 0 {
+        if intended_pos_xy.1 < 0 && boundaries.top == Boundary::Wall {
             intended_pos_xy.1 = 0;
         }
-        if intended_pos_xy.0 >= GRID_WIDTH as i32 {
+        if intended_pos_xy.0 >= GRID_WIDTH as i32 && boundaries.right == Boundary::Wall {
             intended_pos_xy.0 = (GRID_WIDTH - 1) as i32;
         }
-        if intended_pos_xy.1 >= GRID_WIDTH as i32 {
+        if intended_pos_xy.1 >= GRID_WIDTH as i32 && boundaries.bottom == Boundary::Wall {
             intended_pos_xy.1 = (GRID_WIDTH - 1) as i32;
         }
 
-        let steps = line_to_steps(&generate_line(pos_xy, (intended_pos_xy.0 as usize, intended_pos_xy.1 as usize)));
+        let steps = line_to_steps(&generate_line(pos_xy, intended_pos_xy));
 
-        let mut new_point = (pos_xy.0 as i32, pos_xy.1 as i32);
+        let mut new_point = pos_xy;
+        let mut stopped = false;
         for step in &steps {
-            let mut point_xy = (new_point.0 + step.0, new_point.1 + step.1);
+            let mut point_xy = match boundaries.resolve((new_point.0 + step.0, new_point.1 + step.1)) {
+                Crossing::Inside(point_xy) => { point_xy }
+                Crossing::Blocked => {
+                    Self::collide(grid, pos, *step, None, changes);
+                    stopped = true;
+                    break;
+                }
+                Crossing::Gone => { return None; }
+            };
             let mut temp = (point_xy.1 as usize) * GRID_WIDTH + point_xy.0 as usize;
-            if CellType::is_solid(grid.grid[temp].cell_type) {
+            if CellType::is_solid(grid.cells.material(temp), &grid.materials) {
                 if step.0 == 0 || step.1 == 0 {
+                    Self::collide(grid, pos, *step, Some(temp), changes);
+                    stopped = true;
                     break;
                 }
 
                 let mut temp_xy = (point_xy.0, new_point.1);
                 temp = (temp_xy.1 as usize) * GRID_WIDTH + temp_xy.0 as usize;
-                if CellType::is_solid(grid.grid[temp].cell_type) {
+                if CellType::is_solid(grid.cells.material(temp), &grid.materials) {
                     temp_xy = (new_point.0, point_xy.1);
                     temp = (temp_xy.1 as usize) * GRID_WIDTH + temp_xy.0 as usize;
-                    if CellType::is_solid(grid.grid[temp].cell_type) {
+                    if CellType::is_solid(grid.cells.material(temp), &grid.materials) {
+                        let hit = (point_xy.1 as usize) * GRID_WIDTH + point_xy.0 as usize;
+                        Self::collide(grid, pos, *step, Some(hit), changes);
+                        stopped = true;
                         break;
                     }
                 }
@@ -355,59 +709,152 @@ impl Cell {
             new_point = point_xy;
             new_pos = (new_point.1 as usize) * GRID_WIDTH + new_point.0 as usize;
         }
+        // Whatever stopped the cell also took the part of a cell it was on its way through
+        if stopped || intended_pos_xy != unclamped_pos_xy {
+            grid.cells.remainder[pos] = (0.0, 0.0);
+        }
 
-        new_pos
+        Some(new_pos)
     }
 
-    fn get_neighbours(grid: &Grid, pos: usize) -> [(usize, Option<&Cell>);8] {
-        let mut neighbours: [(usize, Option<&Cell>);8] = [(0, None); 8];
-        let mut i = -2;
-        let mut j = -2;
-        let mut index = -1;
-        while i < 1 {
-            i += 1;
-            let row = (pos as i32) + (i * GRID_WIDTH as i32);
-            if row < 0 || row >= GRID_SIZE as i32 {
-                index += 3;
-                continue;
-            }
-            j = -2;
-            while j < 1 {
-                j += 1;
-                let p = row + j;
-                if p == pos as i32 {
+    // Bounces the cell off the cell it ran into, or off the edge of the grid for None, along the
+    // direction it was moving in. A movable cell that was hit takes its share of the momentum.
+    fn collide(grid: &mut Grid, pos: usize, direction: (i32, i32), hit: Option<usize>, changes: &mut Changes) {
+        // Resting cells only press on what is under them, it takes a flying cell to hit something
+        if grid.cells.grounded[pos] {
+            return;
+        }
+        let (cell_type, velocity) = (grid.cells.material(pos), grid.cells.velocity[pos]);
+        let length = ((direction.0 * direction.0 + direction.1 * direction.1) as f32).sqrt();
+        let normal = (direction.0 as f32 / length, direction.1 as f32 / length);
+        let movable = hit.filter(|hit| CellType::is_movable_solid(grid.cells.material(*hit), &grid.materials));
+        let hit_type = hit.map_or(&CELL_STONE, |hit| grid.cells.material(hit)); // The edges are as hard as stone
+        let hit_velocity = movable.map_or((0.0, 0.0), |hit| grid.cells.velocity[hit]);
+
+        let speed = velocity.0 * normal.0 + velocity.1 * normal.1;
+        let closing_speed = speed - (hit_velocity.0 * normal.0 + hit_velocity.1 * normal.1);
+        if closing_speed <= 0.0 {
+            return;
+        }
+        let restitution = Self::restitution(cell_type, hit_type, &grid.materials);
+        let exchanged = (1.0 + restitution) * closing_speed;
+
+        // Heavier cells take the smaller part of the change, fixed cells take none of it
+        let mut share = 1.0;
+        if let Some(hit) = movable {
+            let (mass, hit_mass) = (CellType::get_density(cell_type, &grid.materials), CellType::get_density(hit_type, &grid.materials));
+            share = hit_mass / (mass + hit_mass).max(f32::EPSILON);
+            let pushed = exchanged * (1.0 - share);
+            changes.impulses.append(&mut vec![(hit, (normal.0 * pushed, normal.1 * pushed))]);
+        }
+
+        // Too slow to bounce back, the cell keeps pressing on and the landing logic turns its speed sideways
+        let speed_after = speed - exchanged * share;
+        if speed_after < 0.0 && -speed_after < MIN_BOUNCE_SPEED {
+            return;
+        }
+        grid.cells.velocity[pos] = (velocity.0 - normal.0 * exchanged * share, velocity.1 - normal.1 * exchanged * share);
+    }
+
+    // Share of the speed kept when two materials bounce off each other
+    fn restitution(cell_type: &CellType, other: &CellType, materials: &Materials) -> f32 {
+        (CellType::get_restitution(cell_type, materials) + CellType::get_restitution(other, materials)) / 2.0
+    }
+
+    // Positions and materials of the surrounding cells, the rest of their state is in Grid.cells
+    fn get_neighbours(grid: &Grid, pos: usize) -> [(usize, Option<&'static CellType>);8] {
+        let mut neighbours: [(usize, Option<&'static CellType>);8] = [(0, None); 8];
+        let mut index = 0;
+        for y in -1..=1 {
+            for x in -1..=1 {
+                if x == 0 && y == 0 {
                     continue;
                 }
+                neighbours[index] = Self::get_neighbour(grid, pos, (x, y));
                 index += 1;
-                if row / GRID_WIDTH as i32 != p / GRID_WIDTH as i32 || p < 0 || p >= GRID_SIZE as i32 {
-                    continue;
-                }
-
-                neighbours[index as usize] = (p as usize ,Option::from(&grid.grid[p as usize]));
             }
         }
 
         neighbours
     }
 
-    fn get_neighbour(grid: &Grid, pos: usize, dir: (i8, i8)) -> (usize, Option<&Cell>) {
-        let mut neighbour = (0, None);
+    // Index of the neighbour in the given direction in the array of Cell::get_neighbours
+    fn neighbour_index(dir: (i8, i8)) -> usize {
+        let index = ((dir.1 + 1) * 3 + dir.0 + 1) as usize;
+        if index > 4 { index - 1 } else { index }
+    }
 
+    // Neighbours across a wrapping edge are on the opposite side, past the other edges there are none
+    fn get_neighbour(grid: &Grid, pos: usize, dir: (i8, i8)) -> (usize, Option<&'static CellType>) {
         let x: i32 = ((pos % GRID_WIDTH) as i32) + dir.0 as i32;
         let y: i32 = ((pos / GRID_WIDTH) as i32) + dir.1 as i32;
 
-        if x < 0 || x >= GRID_WIDTH as i32 || y < 0 || y >= GRID_WIDTH as i32 {
-            return neighbour
+        match grid.boundaries.resolve((x, y)) {
+            Crossing::Inside((x, y)) => {
+                let p = (y as usize) * GRID_WIDTH + x as usize;
+                (p, Some(grid.cells.material(p)))
+            }
+            _ => { (0, None) }
         }
+    }
+}
+
+// Directions relative to gravity. "Below" is the neighbour gravity points to the most, the local
+// velocity is split into a sideways part (positive to the right of below) and a falling part.
+struct GravityFrame {
+    strength: f32,
+    down: (f32, f32),
+    side: (f32, f32),
+    below: (i8, i8),
+    below_left: (i8, i8),
+    below_right: (i8, i8),
+    left: (i8, i8),
+    right: (i8, i8)
+}
 
-        let p = (y as usize) * GRID_WIDTH + x as usize;
-        neighbour = (p, Option::from(&grid.grid[p]));
+impl GravityFrame {
+    // Neighbour directions by angle, clockwise starting from the right
+    const DIRECTIONS: [(i8, i8); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
 
-        neighbour
+    // None without gravity
+    fn new(gravity: (f32, f32)) -> Option<GravityFrame> {
+        let strength = gravity.0.hypot(gravity.1);
+        if strength == 0.0 {
+            return None;
+        }
+        let down = (gravity.0 / strength, gravity.1 / strength);
+        let angle = gravity.1.atan2(gravity.0);
+        let index = (angle / std::f32::consts::FRAC_PI_4).round().rem_euclid(8.0) as usize;
+        let direction = |offset: usize| GravityFrame::DIRECTIONS[(index + offset) % 8];
+
+        Some(GravityFrame {
+            strength,
+            down,
+            side: (down.1, -down.0),
+            below: direction(0),
+            below_left: direction(1),
+            below_right: direction(7),
+            left: direction(2),
+            right: direction(6)
+        })
+    }
+
+    fn to_local(&self, velocity: (f32, f32)) -> (f32, f32) {
+        (
+            velocity.0 * self.side.0 + velocity.1 * self.side.1,
+            velocity.0 * self.down.0 + velocity.1 * self.down.1
+        )
+    }
+
+    fn to_world(&self, velocity: (f32, f32)) -> (f32, f32) {
+        (
+            velocity.0 * self.side.0 + velocity.1 * self.down.0,
+            velocity.0 * self.side.1 + velocity.1 * self.down.1
+        )
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum CellType {
     Air,
     Sand,
@@ -415,11 +862,209 @@ enum CellType {
     Water,
     Dirt,
     Coal,
-    Co2
+    Co2,
+    Wood,
+    Debris, // What shattered rigid bodies break into
+    Explosive,
+    Smoke,
+    Spout, // Pours out sand
+    Drain, // Swallows loose cells touching it
+    Script(u8) // Defined by a script, see the scripting module
 }
 
 impl CellType {
-    fn is_solid(cell_type: &CellType) -> bool {
+    const BUILTIN: [CellType; 13] = [
+        CellType::Air,
+        CellType::Sand,
+        CellType::Stone,
+        CellType::Water,
+        CellType::Dirt,
+        CellType::Coal,
+        CellType::Co2,
+        CellType::Wood,
+        CellType::Debris,
+        CellType::Explosive,
+        CellType::Smoke,
+        CellType::Spout,
+        CellType::Drain
+    ];
+
+    fn get_name<'a>(cell_type: &CellType, materials: &'a Materials) -> &'a str {
+        match cell_type {
+            CellType::Air => { "AIR" }
+            CellType::Sand => { "SAND" }
+            CellType::Stone => { "STONE" }
+            CellType::Water => { "WATER" }
+            CellType::Dirt => { "DIRT" }
+            CellType::Coal => { "COAL" }
+            CellType::Co2 => { "CO2" }
+            CellType::Wood => { "WOOD" }
+            CellType::Debris => { "DEBRIS" }
+            CellType::Explosive => { "EXPLOSIVE" }
+            CellType::Smoke => { "SMOKE" }
+            CellType::Spout => { "SPOUT" }
+            CellType::Drain => { "DRAIN" }
+            CellType::Script(id) => { &materials.get(*id).name }
+        }
+    }
+
+    // Built-in materials followed by the ones defined by scripts
+    fn all(materials: &Materials) -> Vec<&'static CellType> {
+        let mut all: Vec<&'static CellType> = vec![];
+        for cell_type in &CellType::BUILTIN {
+            all.append(&mut vec![cell_type]);
+        }
+        for material in materials.iter() {
+            all.append(&mut vec![material.cell_type]);
+        }
+        all
+    }
+
+    // Position of the material in CellType::all, the id it is stored as in the grid
+    fn index(cell_type: &CellType) -> usize {
+        match cell_type {
+            CellType::Air => { 0 }
+            CellType::Sand => { 1 }
+            CellType::Stone => { 2 }
+            CellType::Water => { 3 }
+            CellType::Dirt => { 4 }
+            CellType::Coal => { 5 }
+            CellType::Co2 => { 6 }
+            CellType::Wood => { 7 }
+            CellType::Debris => { 8 }
+            CellType::Explosive => { 9 }
+            CellType::Smoke => { 10 }
+            CellType::Spout => { 11 }
+            CellType::Drain => { 12 }
+            CellType::Script(id) => { CellType::BUILTIN.len() + *id as usize }
+        }
+    }
+
+    // Material at the given position in CellType::all
+    fn from_index(index: usize) -> &'static CellType {
+        let builtin: &'static [CellType] = &CellType::BUILTIN;
+        match builtin.get(index) {
+            Some(cell_type) => { cell_type }
+            None => { CellType::script((index - builtin.len()) as u8) }
+        }
+    }
+
+    // Script materials all have a CellType to refer to, whether a grid has defined them or not
+    fn script(id: u8) -> &'static CellType {
+        const SCRIPT: [CellType; 256] = {
+            let mut types = [CellType::Air; 256];
+            let mut id = 0;
+            while id < types.len() {
+                types[id] = CellType::Script(id as u8);
+                id += 1;
+            }
+            types
+        };
+        static SCRIPT_TYPES: [CellType; 256] = SCRIPT;
+        &SCRIPT_TYPES[id as usize]
+    }
+
+    fn find(name: &str, materials: &Materials) -> Option<&'static CellType> {
+        CellType::all(materials).into_iter().find(|cell_type| CellType::get_name(cell_type, materials).eq_ignore_ascii_case(name))
+    }
+
+    // Character representing the material in scene files
+    fn get_symbol(cell_type: &CellType, materials: &Materials) -> char {
+        match cell_type {
+            CellType::Air => { ' ' }
+            CellType::Sand => { '.' }
+            CellType::Stone => { '#' }
+            CellType::Water => { '~' }
+            CellType::Dirt => { '%' }
+            CellType::Coal => { '*' }
+            CellType::Co2 => { '^' }
+            CellType::Wood => { '=' }
+            CellType::Debris => { ',' }
+            CellType::Explosive => { '!' }
+            CellType::Smoke => { '&' }
+            CellType::Spout => { ':' }
+            CellType::Drain => { '_' }
+            CellType::Script(id) => { materials.get(*id).symbol }
+        }
+    }
+
+    fn from_symbol(symbol: char, materials: &Materials) -> Option<&'static CellType> {
+        match symbol {
+            ' ' => { Some(&CellType::Air) }
+            '.' => { Some(&CellType::Sand) }
+            '#' => { Some(&CellType::Stone) }
+            '~' => { Some(&CellType::Water) }
+            '%' => { Some(&CellType::Dirt) }
+            '*' => { Some(&CellType::Coal) }
+            '^' => { Some(&CellType::Co2) }
+            '=' => { Some(&CellType::Wood) }
+            ',' => { Some(&CellType::Debris) }
+            '!' => { Some(&CellType::Explosive) }
+            '&' => { Some(&CellType::Smoke) }
+            ':' => { Some(&CellType::Spout) }
+            '_' => { Some(&CellType::Drain) }
+            _ => { CellType::all(materials).into_iter().find(|cell_type| CellType::get_symbol(cell_type, materials) == symbol) }
+        }
+    }
+
+    fn get_color(cell_type: &CellType, materials: &Materials) -> [u8;4] {
+        CellType::get_palette(cell_type, materials)[0]
+    }
+
+    // Base colors a new cell picks from
+    fn get_palette<'a>(cell_type: &CellType, materials: &'a Materials) -> &'a [[u8;4]] {
+        match cell_type {
+            CellType::Air => { &[[0, 0, 0, 255]] }
+            CellType::Sand => { &[[252, 186, 3, 255], [240, 174, 22, 255], [255, 201, 44, 255], [228, 166, 12, 255]] }
+            CellType::Stone => { &[[110, 110, 115, 255], [100, 100, 106, 255], [122, 119, 121, 255]] }
+            CellType::Water => { &[[28, 84, 214, 255], [34, 94, 224, 255]] }
+            CellType::Dirt => { &[[89, 44, 20, 255], [101, 53, 25, 255], [78, 39, 17, 255]] }
+            CellType::Coal => { &[[36, 36, 36, 255], [44, 43, 46, 255], [30, 30, 30, 255]] }
+            CellType::Co2 => { &[[48, 52, 58, 255]] }
+            CellType::Wood => { &[[133, 87, 45, 255], [145, 96, 52, 255], [122, 80, 41, 255]] }
+            CellType::Debris => { &[[96, 90, 84, 255]] }
+            CellType::Explosive => { &[[196, 32, 36, 255], [178, 26, 30, 255]] }
+            CellType::Smoke => { &[[92, 92, 96, 255], [80, 80, 84, 255]] }
+            CellType::Spout => { &[[181, 150, 82, 255]] }
+            CellType::Drain => { &[[24, 26, 34, 255]] }
+            CellType::Script(id) => { &materials.get(*id).palette }
+        }
+    }
+
+    // Maximum random brightness offset applied on top of the palette color
+    fn get_color_noise(cell_type: &CellType, materials: &Materials) -> i16 {
+        match cell_type {
+            CellType::Sand => { 12 }
+            CellType::Stone => { 6 }
+            CellType::Water => { 4 }
+            CellType::Dirt => { 8 }
+            CellType::Coal => { 5 }
+            CellType::Wood => { 6 }
+            CellType::Script(id) => { materials.get(*id).color_noise }
+            _ => { 0 }
+        }
+    }
+
+    fn get_texture(cell_type: &CellType) -> Texture {
+        match cell_type {
+            CellType::Stone => { Texture::Strata }
+            CellType::Coal => { Texture::Speckle }
+            CellType::Wood => { Texture::Strata }
+            _ => { Texture::None }
+        }
+    }
+
+    fn pick_color(cell_type: &CellType, materials: &Materials, rng: &mut StdRng) -> [u8;4] {
+        let palette = CellType::get_palette(cell_type, materials);
+        let color = palette[rng.random_range(0..palette.len())];
+        let noise = CellType::get_color_noise(cell_type, materials);
+        if noise == 0 {
+            return color;
+        }
+        offset_color(color, rng.random_range(-noise..=noise))
+    }
+
+    fn is_solid(cell_type: &CellType, materials: &Materials) -> bool {
         match cell_type {
             CellType::Air => { false }
             CellType::Sand => { true }
@@ -428,10 +1073,17 @@ impl CellType {
             CellType::Dirt => { true }
             CellType::Coal => { true }
             CellType::Co2 => { false }
+            CellType::Wood => { true }
+            CellType::Debris => { true }
+            CellType::Explosive => { true }
+            CellType::Smoke => { false }
+            CellType::Spout => { true }
+            CellType::Drain => { true }
+            CellType::Script(id) => { materials.get(*id).solid }
         }
     }
 
-    fn is_movable_solid(cell_type: &CellType) -> bool {
+    fn is_movable_solid(cell_type: &CellType, materials: &Materials) -> bool {
         match cell_type {
             CellType::Air => { false }
             CellType::Sand => { true }
@@ -440,24 +1092,165 @@ impl CellType {
             CellType::Dirt => { true }
             CellType::Coal => { true }
             CellType::Co2 => { false }
+            CellType::Wood => { false }
+            CellType::Debris => { true }
+            CellType::Explosive => { false }
+            CellType::Smoke => { false }
+            CellType::Spout => { false }
+            CellType::Drain => { false }
+            CellType::Script(id) => { materials.get(*id).movable }
         }
     }
 
-    fn get_inertial_resistance(cell_type: &CellType) -> f64 {
+    // Materials without logic of their own
+    fn is_inert(cell_type: &CellType) -> bool {
+        match cell_type {
+            CellType::Air => { true }
+            CellType::Sand => { false }
+            CellType::Stone => { true }
+            CellType::Water => { false }
+            CellType::Dirt => { false }
+            CellType::Coal => { true }
+            CellType::Co2 => { false }
+            CellType::Wood => { true }
+            CellType::Debris => { false }
+            CellType::Explosive => { false }
+            CellType::Smoke => { false }
+            CellType::Spout => { false }
+            CellType::Drain => { false }
+            CellType::Script(_) => { false }
+        }
+    }
+
+    fn get_inertial_resistance(cell_type: &CellType, materials: &Materials) -> f64 {
         match cell_type {
             CellType::Sand => { 0.1 }
             CellType::Dirt => { 0.4 }
             CellType::Coal => { 0.8 }
+            CellType::Debris => { 0.3 }
+            CellType::Script(id) => { materials.get(*id).inertial_resistance }
             _ => { 0.0 }
         }
     }
 
-    fn get_roll_speed(cell_type: &CellType) -> f32 {
+    // Share of the speed kept when bouncing off something
+    fn get_restitution(cell_type: &CellType, materials: &Materials) -> f32 {
+        match cell_type {
+            CellType::Sand => { 0.1 }
+            CellType::Stone => { 0.2 }
+            CellType::Dirt => { 0.05 }
+            CellType::Coal => { 0.2 }
+            CellType::Wood => { 0.3 }
+            CellType::Debris => { 0.3 }
+            CellType::Explosive => { 0.1 }
+            CellType::Spout => { 0.2 }
+            CellType::Drain => { 0.2 }
+            CellType::Script(id) => { materials.get(*id).restitution }
+            _ => { 0.0 }
+        }
+    }
+
+    // Share of the sideways and falling speed lost each tick in the air
+    fn get_drag(cell_type: &CellType, materials: &Materials) -> (f32, f32) {
+        match cell_type {
+            CellType::Sand => { (0.2, 0.01) }
+            CellType::Dirt => { (0.25, 0.01) }
+            CellType::Coal => { (0.2, 0.01) }
+            CellType::Debris => { (0.15, 0.005) }
+            CellType::Script(id) => { materials.get(*id).drag }
+            _ => { (0.0, 0.0) }
+        }
+    }
+
+    // Fastest falling speed, in cells per tick
+    fn get_terminal_velocity(cell_type: &CellType, materials: &Materials) -> f32 {
+        match cell_type {
+            CellType::Sand => { 8.0 }
+            CellType::Dirt => { 8.0 }
+            CellType::Coal => { 8.0 }
+            CellType::Debris => { 10.0 }
+            CellType::Script(id) => { materials.get(*id).terminal_velocity }
+            _ => { 0.0 }
+        }
+    }
+
+    fn get_initial_temperature(cell_type: &CellType, materials: &Materials) -> f32 {
+        match cell_type {
+            CellType::Script(id) => { materials.get(*id).temperature }
+            _ => { AMBIENT_TEMPERATURE }
+        }
+    }
+
+    // Relative to water, decides what rigid bodies float in and how hard loose cells are to push away
+    fn get_density(cell_type: &CellType, materials: &Materials) -> f32 {
+        match cell_type {
+            CellType::Air => { 0.0 }
+            CellType::Sand => { 1.6 }
+            CellType::Stone => { 2.6 }
+            CellType::Water => { 1.0 }
+            CellType::Dirt => { 1.3 }
+            CellType::Coal => { 1.4 }
+            CellType::Co2 => { 0.002 }
+            CellType::Wood => { 0.6 }
+            CellType::Debris => { 1.5 }
+            CellType::Explosive => { 1.6 }
+            CellType::Smoke => { 0.001 }
+            CellType::Spout => { 2.6 }
+            CellType::Drain => { 2.6 }
+            CellType::Script(id) => { materials.get(*id).density }
+        }
+    }
+
+    // Material put out by emitters and the chance to put out a cell each tick
+    fn get_emission(cell_type: &CellType, materials: &Materials) -> Option<(&'static CellType, f64)> {
+        match cell_type {
+            CellType::Spout => { Some((&CELL_SAND, 0.5)) }
+            CellType::Script(id) => { materials.get(*id).emits }
+            _ => { None }
+        }
+    }
+
+    fn get_lifetime(cell_type: &CellType, materials: &Materials) -> Option<Lifetime> {
+        match cell_type {
+            CellType::Smoke => { Some(Lifetime { ticks: (60, 140), decays_into: &CELL_AIR, fades: true }) }
+            CellType::Script(id) => { materials.get(*id).lifetime }
+            _ => { None }
+        }
+    }
+
+    fn is_liquid(cell_type: &CellType, materials: &Materials) -> bool {
+        match cell_type {
+            CellType::Water => { true }
+            CellType::Script(id) => { materials.get(*id).liquid }
+            _ => { false }
+        }
+    }
+
+    fn is_gas(cell_type: &CellType, materials: &Materials) -> bool {
+        match cell_type {
+            CellType::Co2 => { true }
+            CellType::Smoke => { true }
+            CellType::Script(id) => { materials.get(*id).gas }
+            _ => { false }
+        }
+    }
+
+    fn is_drain(cell_type: &CellType, materials: &Materials) -> bool {
+        match cell_type {
+            CellType::Drain => { true }
+            CellType::Script(id) => { materials.get(*id).drain }
+            _ => { false }
+        }
+    }
+
+    fn get_roll_speed(cell_type: &CellType, materials: &Materials) -> f32 {
         match cell_type {
             CellType::Air => { 0.0 }
             CellType::Sand => { 2.0 }
             CellType::Dirt => { 1.5 }
             CellType::Coal => { 1.0 }
+            CellType::Debris => { 1.0 }
+            CellType::Script(id) => { materials.get(*id).roll_speed }
             _ => { 0.0 }
         }
     }
@@ -470,7 +1263,54 @@ struct State {
     window: Option<Window>,
     window_size: LogicalSize<f64>,
     world: World,
-    input: Input
+    input: Input,
+    brush: Brush,
+    hud: Hud,
+    timestep: Timestep,
+    terrain: Terrain // What N generates, set with --terrain
+}
+
+// Accumulates real time and hands it out as fixed simulation ticks
+#[derive(Default)]
+struct Timestep {
+    accumulator: Duration,
+    last_time: Option<Instant>
+}
+
+impl Timestep {
+    // Returns how many ticks to run to catch up with real time
+    fn advance(&mut self) -> u32 {
+        let tick = Duration::from_secs(1) / TICK_RATE;
+        let now = Instant::now();
+        if let Some(last_time) = self.last_time {
+            self.accumulator += now.duration_since(last_time);
+        }
+        self.last_time = Some(now);
+
+        // If ticks take longer than real time, drop the time that can't be caught up instead of falling further behind
+        self.accumulator = self.accumulator.min(tick * MAX_TICKS_PER_FRAME);
+
+        let mut ticks = 0;
+        while self.accumulator >= tick {
+            self.accumulator -= tick;
+            ticks += 1;
+        }
+        ticks
+    }
+}
+
+struct Brush {
+    cell_type: &'static CellType,
+    size: usize
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush {
+            cell_type: &CELL_SAND,
+            size: 1
+        }
+    }
 }
 
 #[derive(Default)]
@@ -478,7 +1318,9 @@ struct Input {
     mouse_position: PhysicalPosition<f32>,
     previous_mouse_position: PhysicalPosition<f32>,
     left_mouse_pressed: bool,
-    right_mouse_pressed: bool
+    right_mouse_pressed: bool,
+    middle_mouse_pressed: bool,
+    shift_pressed: bool
 }
 
 impl ApplicationHandler for State {
@@ -488,8 +1330,7 @@ impl ApplicationHandler for State {
             Window::default_attributes()
                 .with_title("Hello Pixels!")
                 .with_inner_size(self.window_size)
-                .with_min_inner_size(self.window_size)
-                .with_max_inner_size(self.window_size)
+                .with_min_inner_size(LogicalSize::new(BUFFER_WIDTH as f64, BUFFER_WIDTH as f64))
         ).unwrap());
 
         let mut pixels = {
@@ -497,7 +1338,7 @@ impl ApplicationHandler for State {
             let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, self.window.as_ref().unwrap());
             Pixels::new(WIDTH, HEIGHT, surface_texture)
         }.expect("Pixels not initialized!");
-        pixels.resize_buffer(GRID_WIDTH as u32, GRID_WIDTH as u32).expect("Couldn't resize pixels buffer!");
+        pixels.resize_buffer(BUFFER_WIDTH as u32, BUFFER_WIDTH as u32).expect("Couldn't resize pixels buffer!");
         self.world.pixels = Option::from(pixels);
 
         println!("Resumed!");
@@ -505,19 +1346,148 @@ impl ApplicationHandler for State {
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent) {
         // `unwrap` is fine, the window will always be available when
         // receiving a window event.
-        let _window = self.window.as_ref().unwrap();
+        let window = self.window.as_ref().unwrap();
         match event {
             WindowEvent::RedrawRequested => {
+                apply_brush(self);
+                for _ in 0..self.timestep.advance() {
+                    update(self);
+                }
                 render(self, event_loop);
-                update(self, event_loop);
             }
             WindowEvent::CloseRequested => {
                 event_loop.exit()
             }
-            WindowEvent::KeyboardInput { device_id: _device_id, event: _, is_synthetic: _} => {
+            WindowEvent::Resized(size) => {
+                // A minimized window reports a zero size, there is nothing to draw to
+                if size.width > 0 && size.height > 0 {
+                    if let Some(pixels) = self.world.pixels.as_mut() {
+                        if let Err(err) = pixels.resize_surface(size.width, size.height) {
+                            log_error("pixels.resize_surface", err);
+                            event_loop.exit();
+                        }
+                    }
+                }
+                // The old cursor position is in the previous window layout, don't draw a line from it
+                self.input.previous_mouse_position = self.input.mouse_position;
+            }
+            WindowEvent::ScaleFactorChanged {scale_factor: _, inner_size_writer: _} => {
+                self.input.previous_mouse_position = self.input.mouse_position;
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.input.shift_pressed = modifiers.state().shift_key();
+            }
+            WindowEvent::KeyboardInput { device_id: _, event, is_synthetic: _} if event.state == ElementState::Pressed => {
+                if let PhysicalKey::Code(key_code) = event.physical_key {
+                    // Puzzles are only played with the brush
+                    if self.world.puzzle.is_some() && is_sandbox_key(key_code) {
+                        return;
+                    }
+                    // In a puzzle the number keys pick the allowed materials, in the order the puzzle lists them
+                    if let Some(attempt) = &self.world.puzzle {
+                        if let Some(index) = DIGIT_KEYS.iter().position(|key| *key == key_code) {
+                            if let Some(allowance) = attempt.puzzle.allowed.get(index) {
+                                self.brush.cell_type = allowance.cell_type;
+                            }
+                            return;
+                        }
+                    }
+                    let previous_brush = self.brush.cell_type;
+                    match key_code {
+                        KeyCode::Digit1 => { self.brush.cell_type = &CELL_SAND; }
+                        KeyCode::Digit2 => { self.brush.cell_type = &CELL_DIRT; }
+                        KeyCode::Digit3 => { self.brush.cell_type = &CELL_STONE; }
+                        KeyCode::Digit0 => { self.brush.cell_type = &CELL_EXPLOSIVE; }
+                        KeyCode::Minus => { self.brush.cell_type = &CellType::Drain; }
+                        KeyCode::Equal => { self.brush.cell_type = &CellType::Spout; }
+                        KeyCode::KeyQ => { self.brush.cell_type = &CellType::Water; }
+                        KeyCode::Digit4 | KeyCode::Digit5 | KeyCode::Digit6 | KeyCode::Digit7 | KeyCode::Digit8 | KeyCode::Digit9 => {
+                            // The rest of the number keys select script materials in the order they were defined
+                            let index = key_code as usize - KeyCode::Digit4 as usize;
+                            if let Some(material) = self.world.grid.materials.iter().nth(index) {
+                                self.brush.cell_type = material.cell_type;
+                            }
+                        }
+                        KeyCode::KeyC => { spawn_body(self, |center, rng| Body::wooden_crate(center, 10, rng)); }
+                        KeyCode::KeyB => { spawn_body(self, |center, rng| Body::boulder(center, 6, rng)); }
+                        KeyCode::KeyP => { spawn_body(self, |center, rng| Body::plank(center, 30, rng)); }
+                        KeyCode::ArrowDown => { self.world.grid.gravity = (0.0, GRAVITY); }
+                        KeyCode::ArrowUp => { self.world.grid.gravity = (0.0, -GRAVITY); }
+                        KeyCode::ArrowLeft => { self.world.grid.gravity = (-GRAVITY, 0.0); }
+                        KeyCode::ArrowRight => { self.world.grid.gravity = (GRAVITY, 0.0); }
+                        KeyCode::KeyG => { self.world.grid.gravity = (0.0, 0.0); }
+                        // Each edge is cycled on its own, shift picks the opposite one
+                        KeyCode::KeyE => {
+                            let boundaries = &mut self.world.grid.boundaries;
+                            let edge = if self.input.shift_pressed { &mut boundaries.right } else { &mut boundaries.left };
+                            *edge = edge.next();
+                        }
+                        KeyCode::KeyF => {
+                            let boundaries = &mut self.world.grid.boundaries;
+                            let edge = if self.input.shift_pressed { &mut boundaries.top } else { &mut boundaries.bottom };
+                            *edge = edge.next();
+                        }
+                        KeyCode::KeyI => {
+                            // Pushes cells away from the cursor without destroying anything
+                            if let Some(pos) = cursor_grid_pos(self) {
+                                self.world.grid.impulse((pos.0 as f32, pos.1 as f32), EXPLOSION_RADIUS, EXPLOSION_STRENGTH);
+                            }
+                        }
+                        KeyCode::KeyX => {
+                            if let Some(pos) = cursor_grid_pos(self) {
+                                self.world.grid.explode((pos.0 as f32, pos.1 as f32), EXPLOSION_RADIUS, EXPLOSION_STRENGTH);
+                            }
+                        }
+                        KeyCode::BracketLeft => { self.brush.size = (self.brush.size - 1).max(1); }
+                        KeyCode::BracketRight => { self.brush.size = (self.brush.size + 1).min(MAX_BRUSH_SIZE); }
+                        KeyCode::F1 => { self.hud.visible = !self.hud.visible; }
+                        KeyCode::F2 => { self.world.toggle_view(View::Velocity); }
+                        KeyCode::F3 => { self.world.toggle_view(View::FreeFalling); }
+                        KeyCode::F4 => { self.world.toggle_view(View::Grounded); }
+                        KeyCode::F5 => { self.world.toggle_view(View::Moved); }
+                        KeyCode::F6 => { self.world.show_force_fields = !self.world.show_force_fields; }
+                        KeyCode::F7 => { self.world.toggle_view(View::Pressure); }
+                        KeyCode::KeyW => { add_force_field(self, Force::Wind((0.5, 0.0))); }
+                        KeyCode::KeyU => { add_force_field(self, Force::Wind((0.0, -0.5))); }
+                        KeyCode::KeyV => { add_force_field(self, Force::Vortex(0.4)); }
+                        KeyCode::Delete => { self.world.grid.force_fields.clear(); }
+                        KeyCode::KeyN => { replace_grid(self, Grid::from_terrain(rand::random(), &self.terrain)); }
+                        KeyCode::KeyR => {
+                            if let Some(attempt) = self.world.puzzle.as_mut() {
+                                let grid = attempt.start(rand::random());
+                                replace_grid(self, grid);
+                            }
+                        }
+                        _ => {}
+                    }
+                    if self.world.puzzle.as_ref().is_some_and(|attempt| !attempt.allows(self.brush.cell_type)) {
+                        self.brush.cell_type = previous_brush;
+                    }
+                }
             }
             WindowEvent::CursorMoved {device_id: _, position} => {
-                self.input.mouse_position = <(f32, f32)>::from(position).into();
+                let mouse_position: PhysicalPosition<f32> = <(f32, f32)>::from(position).into();
+                if self.input.middle_mouse_pressed {
+                    // Convert the drag from window pixels to frame buffer pixels
+                    let scale = buffer_scale(window.inner_size());
+                    self.world.camera.pan((
+                        (mouse_position.x - self.input.mouse_position.x) / scale,
+                        (mouse_position.y - self.input.mouse_position.y) / scale
+                    ));
+                }
+                self.input.mouse_position = mouse_position;
+            }
+            WindowEvent::MouseWheel {device_id: _, delta, phase: _} => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => { y }
+                    MouseScrollDelta::PixelDelta(position) => { position.y as f32 / PIXELS_PER_SCROLL_LINE }
+                };
+                if let Some(pixels) = self.world.pixels.as_ref() {
+                    let pixel = pixels
+                        .window_pos_to_pixel((self.input.mouse_position.x, self.input.mouse_position.y))
+                        .unwrap_or_else(|pos| pixels.clamp_pixel_pos(pos));
+                    self.world.camera.zoom_at((pixel.0 as f32, pixel.1 as f32), steps);
+                }
             }
             WindowEvent::MouseInput {device_id: _, button, state} => {
                 if button == MouseButton::Left {
@@ -540,6 +1510,16 @@ impl ApplicationHandler for State {
                         }
                     }
                 }
+                else if button == MouseButton::Middle {
+                    match state {
+                        ElementState::Pressed => {
+                            self.input.middle_mouse_pressed = true;
+                        }
+                        ElementState::Released => {
+                            self.input.middle_mouse_pressed = false;
+                        }
+                    }
+                }
             }
             _ => ()
         }
@@ -557,55 +1537,220 @@ impl ApplicationHandler for State {
 
 fn main() {
     env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match headless::Options::parse(&args) {
+        Ok(Some(options)) => {
+            if let Err(err) = headless::run(&options) {
+                log_error("headless::run", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(None) => {}
+        Err(message) => {
+            eprintln!("{message}\n\n{}", headless::USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    if let Err(message) = check_window_args(&args) {
+        eprintln!("{message}\n\n{}", headless::USAGE);
+        std::process::exit(2);
+    }
+
     let event_loop = EventLoop::new().unwrap();
-    let mut state = State::default();
-    state.window_size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
+    let mut state = State {
+        window_size: LogicalSize::new(WIDTH as f64, HEIGHT as f64),
+        ..Default::default()
+    };
+    if let Some(value) = flag_value(&args, "--terrain") {
+        match Terrain::parse(value) {
+            Some(terrain) => {
+                state.terrain = terrain;
+                state.world.grid = Grid::from_terrain(rand::random(), &terrain);
+            }
+            None => {
+                eprintln!("invalid terrain '{value}'\n\n{}", headless::USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(path) = flag_value(&args, "--script") {
+        match Scripts::load_file(Path::new(path)) {
+            Ok(scripts) => { state.world.grid.set_scripts(Rc::new(scripts)); }
+            Err(err) => {
+                log_error("Scripts::load_file", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    // After the scripts, puzzles may use their materials
+    if let Some(path) = flag_value(&args, "--puzzle") {
+        match Puzzle::load_file(Path::new(path), state.world.grid.scripts.clone()) {
+            Ok(puzzle) => {
+                let mut attempt = Attempt::new(puzzle);
+                replace_grid(&mut state, attempt.start(rand::random()));
+                if let Some(allowance) = attempt.puzzle.allowed.first() {
+                    state.brush.cell_type = allowance.cell_type;
+                }
+                state.world.puzzle = Some(attempt);
+            }
+            Err(err) => {
+                log_error("Puzzle::load_file", err);
+                std::process::exit(1);
+            }
+        }
+    }
     let _ = event_loop.run_app(&mut state);
 }
 
-fn update(state: &mut State, _event_loop: &ActiveEventLoop) {
+// Flags the window understands, each followed by a value
+const WINDOW_FLAGS: [&str; 3] = ["--script", "--puzzle", "--terrain"];
+
+// Rejects misspelled flags and flags without a value, like headless mode does
+fn check_window_args(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !WINDOW_FLAGS.contains(&arg.as_str()) {
+            return Err(format!("unknown option {arg}"));
+        }
+        args.next().ok_or(format!("missing value for {arg}"))?;
+    }
+    Ok(())
+}
+
+// Value given after the flag, the arguments have been checked to come in pairs
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.chunks(2).find(|pair| pair[0] == name).and_then(|pair| pair.get(1)).map(String::as_str)
+}
+
+fn apply_brush(state: &mut State) {
     if state.input.left_mouse_pressed || state.input.right_mouse_pressed {
-        if state.world.pixels.is_some() {
+        if let Some(pixels) = state.world.pixels.as_ref() {
             let pixel_pos1 =
-                state.world.pixels.as_mut().unwrap()
-                    .window_pos_to_pixel((state.input.previous_mouse_position.x, state.input.previous_mouse_position.y));
+                pixels.window_pos_to_pixel((state.input.previous_mouse_position.x, state.input.previous_mouse_position.y));
             let pixel_pos2 =
-                state.world.pixels.as_mut().unwrap()
-                    .window_pos_to_pixel((state.input.mouse_position.x, state.input.mouse_position.y));
-            if pixel_pos1.is_ok() && pixel_pos2.is_ok() {
-                let pos1 = pixel_pos1.unwrap();
-                let pos2 = pixel_pos2.unwrap();
-                if state.input.left_mouse_pressed {
-                    state.world.grid.place_line(pos1, pos2, &CELL_SAND);
+                pixels.window_pos_to_pixel((state.input.mouse_position.x, state.input.mouse_position.y));
+            if let (Ok(pixel1), Ok(pixel2)) = (pixel_pos1, pixel_pos2) {
+                let pos1 = state.world.camera.to_grid(pixel1);
+                let pos2 = state.world.camera.to_grid(pixel2);
+                if let Some(attempt) = state.world.puzzle.as_mut() {
+                    // Only the brush material goes against the allowance, the right button does nothing
+                    if state.input.left_mouse_pressed {
+                        attempt.place_line(&mut state.world.grid, pos1, pos2, state.brush.size, state.brush.cell_type);
+                    }
+                }
+                else if state.input.left_mouse_pressed {
+                    state.world.grid.place_line(pos1, pos2, state.brush.size, state.brush.cell_type);
                 }
                 else {
-                    state.world.grid.place_line(pos1, pos2, &CELL_DIRT);
+                    state.world.grid.place_line(pos1, pos2, state.brush.size, &CELL_DIRT);
                 }
             }
         }
     }
 
     state.input.previous_mouse_position = state.input.mouse_position;
+}
+
+// Grid cell under the cursor, None when the cursor is outside of the frame
+fn cursor_grid_pos(state: &State) -> Option<(usize, usize)> {
+    let pixels = state.world.pixels.as_ref()?;
+    let pixel = pixels.window_pos_to_pixel((state.input.mouse_position.x, state.input.mouse_position.y)).ok()?;
+    Some(state.world.camera.to_grid(pixel))
+}
+
+// Keys that change the world some other way than with the brush, or the rules it follows
+const DIGIT_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4, KeyCode::Digit5,
+    KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9
+];
+
+fn is_sandbox_key(key_code: KeyCode) -> bool {
+    matches!(
+        key_code,
+        KeyCode::KeyC | KeyCode::KeyB | KeyCode::KeyP | KeyCode::KeyN | KeyCode::KeyG | KeyCode::KeyE | KeyCode::KeyF
+            | KeyCode::KeyI | KeyCode::KeyX | KeyCode::KeyW | KeyCode::KeyU | KeyCode::KeyV | KeyCode::Delete
+            | KeyCode::ArrowDown | KeyCode::ArrowUp | KeyCode::ArrowLeft | KeyCode::ArrowRight
+    )
+}
 
+// Swaps in a new grid, keeping the script materials and the physics settings of the old one
+fn replace_grid(state: &mut State, grid: Grid) {
+    let old = std::mem::replace(&mut state.world.grid, grid);
+    state.world.grid.scripts = old.scripts;
+    state.world.grid.materials = old.materials;
+    state.world.grid.gravity = old.gravity;
+    state.world.grid.boundaries = old.boundaries;
+}
+
+fn add_force_field(state: &mut State, force: Force) {
+    if let Some(pos) = cursor_grid_pos(state) {
+        state.world.grid.add_force_field(ForceField::new(pos, (FORCE_FIELD_SIZE, FORCE_FIELD_SIZE), force));
+        state.world.show_force_fields = true;
+    }
+}
+
+// Drops a new rigid body at the cursor
+fn spawn_body(state: &mut State, make_body: fn((f32, f32), &mut StdRng) -> Body) {
+    if let Some(pos) = cursor_grid_pos(state) {
+        let body = make_body((pos.0 as f32, pos.1 as f32), &mut state.world.grid.rng);
+        state.world.grid.add_body(body);
+    }
+}
+
+fn update(state: &mut State) {
+    let tick_start = Instant::now();
     state.world.grid.execute_logic();
+    if let Some(attempt) = state.world.puzzle.as_mut() {
+        attempt.tick(&state.world.grid);
+    }
+    state.hud.record_tick(tick_start.elapsed());
 }
 
 fn render(state: &mut State, event_loop: &ActiveEventLoop) {
+    state.hud.record_frame();
     state.world.draw();
+    if state.hud.visible {
+        state.world.draw_hud(&state.hud, &state.brush);
+    }
     if let Err(err) = state.world.pixels.as_ref().unwrap().render() {
         log_error("pixels.render", err);
         event_loop.exit();
-        return;
     }
 }
 
-fn generate_line(pos1: (usize, usize), pos2: (usize, usize)) -> Vec<(i32, i32)> {
+// Window pixels per frame buffer pixel. Pixels keeps the aspect ratio and only scales by whole numbers.
+fn buffer_scale(window_size: PhysicalSize<u32>) -> f32 {
+    let width_ratio = window_size.width as f32 / BUFFER_WIDTH as f32;
+    let height_ratio = window_size.height as f32 / BUFFER_WIDTH as f32;
+    width_ratio.min(height_ratio).floor().max(1.0)
+}
+
+// Cells covered by a round brush of the given size, cut off at the edges of the grid
+fn circle_cells(center: (i32, i32), size: usize) -> Vec<usize> {
+    let mut cells = vec![];
+    let radius = size as i32 - 1;
+    for y in (center.1 - radius)..=(center.1 + radius) {
+        for x in (center.0 - radius)..=(center.0 + radius) {
+            if x < 0 || x >= GRID_WIDTH as i32 || y < 0 || y >= GRID_WIDTH as i32 {
+                continue;
+            }
+            let (dx, dy) = (x - center.0, y - center.1);
+            if dx * dx + dy * dy <= radius * radius {
+                cells.append(&mut vec![(y as usize) * GRID_WIDTH + (x as usize)]);
+            }
+        }
+    }
+    cells
+}
+
+fn generate_line(pos1: (i32, i32), pos2: (i32, i32)) -> Vec<(i32, i32)> {
     let mut points = vec![];
 
-    let mut x1:i32 = pos1.0 as i32;
-    let x2:i32 = pos2.0 as i32;
-    let mut y1:i32 = pos1.1 as i32;
-    let y2:i32 = pos2.1 as i32;
+    let (mut x1, x2) = (pos1.0, pos2.0);
+    let (mut y1, y2) = (pos1.1, pos2.1);
 
     let dx:i32 = x1.abs_diff(x2) as i32;
     let mut xpositive = true;
@@ -629,7 +1774,7 @@ fn generate_line(pos1: (usize, usize), pos2: (usize, usize)) -> Vec<(i32, i32)>
             if x1 == x2{
                 break;
             }
-            error = error + dy;
+            error += dy;
             if xpositive {
                 x1 += 1;
             }
@@ -642,7 +1787,7 @@ fn generate_line(pos1: (usize, usize), pos2: (usize, usize)) -> Vec<(i32, i32)>
             if y1 == y2{
                 break
             }
-            error = error + dx;
+            error += dx;
             if ypositive {
                 y1 += 1;
             }
@@ -654,18 +1799,32 @@ fn generate_line(pos1: (usize, usize), pos2: (usize, usize)) -> Vec<(i32, i32)>
 
     points
 }
-fn line_to_steps(line: &Vec<(i32, i32)>) -> Vec<(i32, i32)> {
+fn line_to_steps(line: &[(i32, i32)]) -> Vec<(i32, i32)> {
     let mut steps = vec![];
 
     let mut previous_point = line[0];
     for point in &line[1..] {
         steps.append(&mut vec![(point.0 - previous_point.0, point.1 - previous_point.1)]);
-        previous_point = point.clone();
+        previous_point = *point;
     }
 
     steps
 }
 
+// Maps 0.0..=1.0 to blue, cyan, green, yellow and red
+fn heatmap(t: f32) -> [u8;4] {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let segment = (t as usize).min(3);
+    let f = t - segment as f32;
+    let (r, g, b) = match segment {
+        0 => { (0.0, f, 1.0) }
+        1 => { (0.0, 1.0, 1.0 - f) }
+        2 => { (f, 1.0, 0.0) }
+        _ => { (1.0, 1.0 - f, 0.0) }
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255]
+}
+
 fn log_error<E: std::error::Error + 'static>(method_name: &str, err: E) {
     error!("{method_name}() failed: {err}");
     for source in err.sources().skip(1) {
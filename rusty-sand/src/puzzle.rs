@@ -0,0 +1,299 @@
+// Puzzles combine a starting scene with a limited set of materials the player may place and goals
+// to reach with them, like filling a container with water. A puzzle file starts with settings,
+// one per line, followed by the scene:
+//
+//   # Lines starting with '#' are comments
+//   title: Fill the tank
+//   allow: water 600          Material the player may place and how much, no amount is unlimited
+//   area: 0,0 199,80          Corners of the area the player may place in, anywhere if left out
+//   goal: 500 water in 80,150 119,179
+//   scene:
+//   ...rows in the scene format...
+//
+// Goals count the cells of a material inside a zone and are checked every tick. The puzzle is
+// solved once all of them are reached at the same time.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::camera::Camera;
+use crate::scene::SceneError;
+use crate::scripting::Scripts;
+use crate::texture::blend_color;
+use crate::{circle_cells, generate_line, CellType, Grid, BUFFER_WIDTH, GRID_WIDTH};
+
+const ZONE_OPACITY: f32 = 0.25;
+const GOAL_COLOR: [u8; 4] = [255, 200, 40, 255];
+const REACHED_COLOR: [u8; 4] = [60, 230, 90, 255];
+const AREA_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+#[derive(Debug)]
+pub enum PuzzleError {
+    Io(PathBuf, io::Error),
+    InvalidLine { line: usize, text: String },
+    UnknownMaterial { line: usize, name: String },
+    NoScene,
+    NoGoals,
+    Scene(SceneError)
+}
+
+impl fmt::Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PuzzleError::Io(path, _) => { write!(f, "couldn't read puzzle {}", path.display()) }
+            PuzzleError::InvalidLine { line, text } => { write!(f, "can't read line {line}: '{text}'") }
+            PuzzleError::UnknownMaterial { line, name } => { write!(f, "unknown material '{name}' at line {line}") }
+            PuzzleError::NoScene => { write!(f, "puzzle has no 'scene:' line") }
+            PuzzleError::NoGoals => { write!(f, "puzzle has no goals") }
+            PuzzleError::Scene(_) => { write!(f, "invalid scene") }
+        }
+    }
+}
+
+impl std::error::Error for PuzzleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PuzzleError::Io(_, err) => { Some(err) }
+            PuzzleError::Scene(err) => { Some(err) }
+            _ => { None }
+        }
+    }
+}
+
+// Rectangle of cells, both corners included
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Zone {
+    min: (usize, usize),
+    max: (usize, usize)
+}
+
+impl Zone {
+    // Two corners like "10,20 40,60", in any order
+    fn parse(value: &str) -> Option<Zone> {
+        let (first, second) = value.trim().split_once(' ')?;
+        let (x1, y1) = parse_point(first)?;
+        let (x2, y2) = parse_point(second)?;
+        Some(Zone { min: (x1.min(x2), y1.min(y2)), max: (x1.max(x2), y1.max(y2)) })
+    }
+
+    fn contains(&self, pos: usize) -> bool {
+        let (x, y) = (pos % GRID_WIDTH, pos / GRID_WIDTH);
+        x >= self.min.0 && x <= self.max.0 && y >= self.min.1 && y <= self.max.1
+    }
+
+    fn is_edge(&self, pos: (usize, usize)) -> bool {
+        (pos.0 == self.min.0 || pos.0 == self.max.0 || pos.1 == self.min.1 || pos.1 == self.max.1)
+            && self.contains(pos.1 * GRID_WIDTH + pos.0)
+    }
+}
+
+fn parse_point(value: &str) -> Option<(usize, usize)> {
+    let (x, y) = value.trim().split_once(',')?;
+    let point = (x.trim().parse().ok()?, y.trim().parse().ok()?);
+    if point.0 >= GRID_WIDTH || point.1 >= GRID_WIDTH {
+        return None;
+    }
+    Some(point)
+}
+
+pub struct Allowance {
+    pub cell_type: &'static CellType,
+    pub amount: Option<usize> // Cells the player may place, unlimited if None
+}
+
+pub struct Goal {
+    pub cell_type: &'static CellType,
+    pub count: usize, // Cells needed inside the zone
+    pub zone: Zone
+}
+
+impl Goal {
+    fn progress(&self, grid: &Grid) -> usize {
+        let mut count = 0;
+        for y in self.zone.min.1..=self.zone.max.1 {
+            for x in self.zone.min.0..=self.zone.max.0 {
+                if grid.cells.material(y * GRID_WIDTH + x).eq(self.cell_type) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+pub struct Puzzle {
+    pub title: String,
+    pub allowed: Vec<Allowance>,
+    pub area: Option<Zone>,
+    pub goals: Vec<Goal>,
+    scene: String,
+    scripts: Option<Rc<Scripts>> // Their materials can be used in the puzzle
+}
+
+impl Puzzle {
+    pub fn load_file(path: &Path, scripts: Option<Rc<Scripts>>) -> Result<Puzzle, PuzzleError> {
+        let text = fs::read_to_string(path).map_err(|err| PuzzleError::Io(path.to_path_buf(), err))?;
+        Puzzle::parse(&text, scripts)
+    }
+
+    // Materials are looked up by name, both the built-in ones and those of the scripts
+    pub fn parse(text: &str, scripts: Option<Rc<Scripts>>) -> Result<Puzzle, PuzzleError> {
+        let materials = scripts.as_ref().map(|scripts| scripts.materials.clone()).unwrap_or_default();
+        let mut puzzle = Puzzle { title: String::new(), allowed: vec![], area: None, goals: vec![], scene: String::new(), scripts };
+        let mut lines = text.lines().enumerate();
+        let mut scene_start = None;
+
+        for (index, text) in lines.by_ref() {
+            let line = index + 1;
+            let trimmed = text.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let invalid = || PuzzleError::InvalidLine { line, text: text.to_string() };
+            let material = |name: &str| CellType::find(name, &materials).ok_or(PuzzleError::UnknownMaterial { line, name: name.to_string() });
+
+            let (key, value) = trimmed.split_once(':').ok_or_else(invalid)?;
+            let value = value.trim();
+            match key.trim() {
+                "title" => { puzzle.title = value.to_string(); }
+                "allow" => {
+                    let (name, amount) = match value.split_once(' ') {
+                        Some((name, amount)) => { (name, Some(amount.trim().parse().map_err(|_| invalid())?)) }
+                        None => { (value, None) }
+                    };
+                    puzzle.allowed.append(&mut vec![Allowance { cell_type: material(name)?, amount }]);
+                }
+                "area" => { puzzle.area = Some(Zone::parse(value).ok_or_else(invalid)?); }
+                "goal" => {
+                    // "<count> <material> in <zone>"
+                    let (count, rest) = value.split_once(' ').ok_or_else(invalid)?;
+                    let (name, zone) = rest.split_once(" in ").ok_or_else(invalid)?;
+                    puzzle.goals.append(&mut vec![Goal {
+                        cell_type: material(name.trim())?,
+                        count: count.parse().map_err(|_| invalid())?,
+                        zone: Zone::parse(zone).ok_or_else(invalid)?
+                    }]);
+                }
+                "scene" => {
+                    scene_start = Some(line);
+                    break;
+                }
+                _ => { return Err(invalid()); }
+            }
+        }
+
+        let scene_start = scene_start.ok_or(PuzzleError::NoScene)?;
+        if puzzle.goals.is_empty() {
+            return Err(PuzzleError::NoGoals);
+        }
+        let rows: Vec<&str> = lines.map(|(_, row)| row).collect();
+        puzzle.scene = rows.join("\n");
+
+        // Errors point at the line in the puzzle file, not in the scene
+        Grid::from_scene(&puzzle.scene, 0, puzzle.scripts.clone()).map_err(|err| match err {
+            SceneError::UnknownSymbol { line, column, symbol } => {
+                PuzzleError::Scene(SceneError::UnknownSymbol { line: line + scene_start, column, symbol })
+            }
+            err => { PuzzleError::Scene(err) }
+        })?;
+
+        Ok(puzzle)
+    }
+}
+
+// A puzzle being played: what is left to place and how far along the goals are
+pub struct Attempt {
+    pub puzzle: Puzzle,
+    pub remaining: Vec<Option<usize>>, // Per allowance
+    pub progress: Vec<usize>, // Per goal, as of the last tick
+    pub ticks: u64,
+    pub solved_at: Option<u64> // Tick the goals were first all reached at
+}
+
+impl Attempt {
+    pub fn new(puzzle: Puzzle) -> Attempt {
+        let mut attempt = Attempt { puzzle, remaining: vec![], progress: vec![], ticks: 0, solved_at: None };
+        attempt.reset();
+        attempt
+    }
+
+    fn reset(&mut self) {
+        self.remaining = self.puzzle.allowed.iter().map(|allowance| allowance.amount).collect();
+        self.progress = vec![0; self.puzzle.goals.len()];
+        self.ticks = 0;
+        self.solved_at = None;
+    }
+
+    // Starts over with everything placed so far gone
+    pub fn start(&mut self, seed: u64) -> Grid {
+        self.reset();
+        let grid = Grid::from_scene(&self.puzzle.scene, seed, self.puzzle.scripts.clone()).expect("checked when the puzzle was parsed");
+        self.progress = self.puzzle.goals.iter().map(|goal| goal.progress(&grid)).collect();
+        grid
+    }
+
+    pub fn allows(&self, cell_type: &CellType) -> bool {
+        self.puzzle.allowed.iter().any(|allowance| allowance.cell_type.eq(cell_type))
+    }
+
+    // Like Grid::place_line, but only inside the area and until the allowance runs out. Only air is
+    // filled, so nothing that was there from the start can be erased or replaced.
+    pub fn place_line(&mut self, grid: &mut Grid, pos1: (usize, usize), pos2: (usize, usize), size: usize, cell_type: &'static CellType) {
+        let Some(index) = self.puzzle.allowed.iter().position(|allowance| allowance.cell_type.eq(cell_type)) else {
+            return;
+        };
+        for point in generate_line((pos1.0 as i32, pos1.1 as i32), (pos2.0 as i32, pos2.1 as i32)) {
+            for pos in circle_cells(point, size) {
+                if self.remaining[index] == Some(0) {
+                    return;
+                }
+                if !grid.cells.is_air(pos) || self.puzzle.area.is_some_and(|area| !area.contains(pos)) {
+                    continue;
+                }
+                grid.place(pos, cell_type);
+                if let Some(remaining) = &mut self.remaining[index] {
+                    *remaining -= 1;
+                }
+            }
+        }
+    }
+
+    // Checks the goals, called after every tick
+    pub fn tick(&mut self, grid: &Grid) {
+        self.ticks += 1;
+        self.progress = self.puzzle.goals.iter().map(|goal| goal.progress(grid)).collect();
+        if self.solved_at.is_none() && self.goals_reached().all(|reached| reached) {
+            self.solved_at = Some(self.ticks);
+        }
+    }
+
+    pub fn goals_reached(&self) -> impl Iterator<Item = bool> + '_ {
+        self.puzzle.goals.iter().zip(&self.progress).map(|(goal, progress)| *progress >= goal.count)
+    }
+}
+
+// Tints the goal zones, green once reached, and outlines the area the player may place in
+pub fn draw_puzzle_overlay(frame: &mut [u8], attempt: &Attempt, camera: &Camera) {
+    let reached: Vec<bool> = attempt.goals_reached().collect();
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        let (x, y) = camera.to_grid((i % BUFFER_WIDTH, i / BUFFER_WIDTH));
+        let mut overlay = None;
+        if attempt.puzzle.area.is_some_and(|area| area.is_edge((x, y))) {
+            overlay = Some((AREA_COLOR, ZONE_OPACITY));
+        }
+        for (goal, reached) in attempt.puzzle.goals.iter().zip(&reached) {
+            if goal.zone.contains(y * GRID_WIDTH + x) {
+                let color = if *reached { REACHED_COLOR } else { GOAL_COLOR };
+                let opacity = if goal.zone.is_edge((x, y)) { ZONE_OPACITY * 3.0 } else { ZONE_OPACITY };
+                overlay = Some((color, opacity));
+            }
+        }
+
+        if let Some((color, opacity)) = overlay {
+            blend_color(pixel, color, opacity);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Declarative reactions between touching materials, like water cooling lava into stone. Every tick
+// each cell of the first reactant gets a chance to react with every touching cell of the second one,
+// and a cell takes part in at most one reaction per tick.
+
+use rand::Rng;
+
+use crate::{Cell, CellType, Grid, GRID_SIZE, IGNITION_TEMPERATURE};
+
+#[derive(Copy, Clone)]
+pub struct Reaction {
+    pub reactants: (&'static CellType, &'static CellType),
+    pub products: (&'static CellType, &'static CellType),
+    pub probability: f64,
+    // Compared to the temperature of the first reactant
+    pub min_temperature: Option<f32>,
+    pub max_temperature: Option<f32>
+}
+
+const BUILTIN_REACTIONS: [Reaction; 1] = [
+    // Hot coal burns away where it touches air, giving off smoke
+    Reaction {
+        reactants: (&CellType::Coal, &CellType::Air),
+        products: (&CellType::Co2, &CellType::Smoke),
+        probability: 0.05,
+        min_temperature: Some(IGNITION_TEMPERATURE),
+        max_temperature: None
+    }
+];
+
+impl Reaction {
+    fn applies(&self, first: &CellType, second: &CellType, temperature: f32) -> bool {
+        if !first.eq(self.reactants.0) || !second.eq(self.reactants.1) {
+            return false;
+        }
+        self.min_temperature.is_none_or(|min| temperature >= min) && self.max_temperature.is_none_or(|max| temperature <= max)
+    }
+}
+
+impl Grid {
+    fn reactions(&self) -> Vec<Reaction> {
+        let mut reactions = BUILTIN_REACTIONS.to_vec();
+        if let Some(scripts) = &self.scripts {
+            reactions.append(&mut scripts.reactions.clone());
+        }
+        reactions
+    }
+
+    pub fn apply_reactions(&mut self) {
+        let reactions = self.reactions();
+        let starters: Vec<CellType> = reactions.iter().map(|reaction| *reaction.reactants.0).collect();
+        let mut reacted = vec![false; GRID_SIZE];
+        for pos in 0..GRID_SIZE {
+            // Most cells can't start a reaction, they are skipped without looking at their neighbours
+            if reacted[pos] || !starters.contains(self.cells.material(pos)) {
+                continue;
+            }
+
+            let neighbours: Vec<usize> = Cell::get_neighbours(self, pos).iter().filter(|(_, n)| n.is_some()).map(|(p, _)| *p).collect();
+            for neighbour_pos in neighbours {
+                if reacted[neighbour_pos] {
+                    continue;
+                }
+                let (cell, neighbour) = (self.cells.material(pos), self.cells.material(neighbour_pos));
+                let temperatures = (self.cells.temperature[pos], self.cells.temperature[neighbour_pos]);
+                let Some(reaction) = reactions.iter().find(|reaction| reaction.applies(cell, neighbour, temperatures.0)) else {
+                    continue;
+                };
+                if !self.rng.random_bool(reaction.probability) {
+                    continue;
+                }
+
+                // The products share the heat of the reactants
+                let temperature = (temperatures.0 + temperatures.1) / 2.0;
+                self.react(pos, reaction.products.0, temperature);
+                self.react(neighbour_pos, reaction.products.1, temperature);
+                reacted[pos] = true;
+                reacted[neighbour_pos] = true;
+                break;
+            }
+        }
+    }
+
+    fn react(&mut self, pos: usize, product: &'static CellType, temperature: f32) {
+        if !self.cells.material(pos).eq(product) {
+            self.cells.set(pos, Cell::new_at(product, pos, &self.materials, &mut self.rng));
+        }
+        self.cells.temperature[pos] = temperature;
+    }
+}
@@ -0,0 +1,442 @@
+// Rigid bodies made of grid cells, such as crates, boulders and planks.
+//
+// A body keeps its own shape and moves as a whole, with a position, velocity, angle and angular
+// velocity. Every tick its cells are lifted out of the grid, the body is moved and rotated, and the
+// cells are stamped back at the new pose. Cells that can't move (stone, wood, other bodies) stop it,
+// loose material like sand slows it down and gets pushed out of the way, and liquids around it
+// carry it depending on its density. Cells erased from a body with the brush are gone from its shape.
+//
+// Stamped cells are ordinary cells of the body's material, so a saved scene keeps them as static cells.
+
+use std::collections::{BTreeSet, HashSet};
+
+use rand::rngs::StdRng;
+
+use crate::scripting::Materials;
+use crate::texture::scale_color;
+use crate::{Cell, CellType, Grid, CELL_AIR, GRID_WIDTH};
+
+const RESTITUTION: f32 = 0.2;
+const FRICTION: f32 = 0.8;
+const ANGULAR_DAMPING: f32 = 0.98;
+// Impacts slower than this don't bounce
+const BOUNCE_SPEED: f32 = 1.0;
+// Loose material only gets pushed away by bodies moving faster than this, otherwise it carries them
+const PLOUGH_SPEED: f32 = 1.0;
+// Speed lost for every pushed away loose cell, scaled by its density over the body's mass
+const LOOSE_RESISTANCE: f32 = 4.0;
+const LIQUID_DRAG: f32 = 0.1;
+// Angular acceleration of a body resting on cells only on one side of its center
+const TIP_ACCELERATION: f32 = 0.01;
+// Pushed away cells move to the nearest free cell at most this far away, or up the column otherwise
+const MAX_DISPLACEMENT: i32 = 6;
+// Speed of shattered cells flying away from the center, per cell of distance
+const SHATTER_SPREAD: f32 = 0.3;
+
+const CELL_WOOD: CellType = CellType::Wood;
+const CELL_DEBRIS: CellType = CellType::Debris;
+
+pub struct Body {
+    material: &'static CellType,
+    width: usize,
+    height: usize,
+    shape: Vec<Option<[u8;4]>>, // Colors of the cells, row by row
+    position: (f32, f32), // Grid position of the center
+    pub velocity: (f32, f32),
+    pub angle: f32,
+    pub angular_velocity: f32,
+    pub shatter_speed: Option<f32>, // Breaks into debris when hitting something at least this fast
+    stamped: Vec<(usize, usize)>, // Grid positions of the stamped cells and their index in the shape
+    vacated: Vec<(i32, i32)> // Cells lifted this tick, pushed away cells fill them first
+}
+
+// Cells of the grid a body would overlap at some pose
+#[derive(Default)]
+struct Probe {
+    blocking: Vec<(i32, i32)>,
+    loose: Vec<(i32, i32)>,
+    loose_mass: f32
+}
+
+impl Body {
+    // Bodies are made of built-in materials, so they don't need a grid's script materials to look them up
+    pub fn new(material: &'static CellType, width: usize, height: usize, center: (f32, f32), rng: &mut StdRng, filled: impl Fn(usize, usize) -> bool) -> Body {
+        let mut shape = vec![None; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                if filled(x, y) {
+                    // Textured in body space, so the pattern turns with the body
+                    shape[y * width + x] = Some(CellType::get_texture(material).apply(CellType::pick_color(material, &Materials::default(), rng), (x, y)));
+                }
+            }
+        }
+
+        Body {
+            material,
+            width,
+            height,
+            shape,
+            position: center,
+            velocity: (0.0, 0.0),
+            angle: 0.0,
+            angular_velocity: 0.0,
+            shatter_speed: None,
+            stamped: vec![],
+            vacated: vec![]
+        }
+    }
+
+    // Square wooden crate with a darker frame
+    pub fn wooden_crate(center: (f32, f32), size: usize, rng: &mut StdRng) -> Body {
+        let mut body = Body::new(&CELL_WOOD, size, size, center, rng, |_, _| true);
+        for y in 0..size {
+            for x in 0..size {
+                if x == 0 || y == 0 || x == size - 1 || y == size - 1 || x == y || x + y == size - 1 {
+                    body.shape[y * size + x] = body.shape[y * size + x].map(|color| scale_color(color, 0.7));
+                }
+            }
+        }
+        body.shatter_speed = Some(6.0);
+        body
+    }
+
+    pub fn boulder(center: (f32, f32), radius: usize, rng: &mut StdRng) -> Body {
+        let size = radius * 2 + 1;
+        let mut body = Body::new(&CellType::Stone, size, size, center, rng, |x, y| {
+            let (dx, dy) = (x as i32 - radius as i32, y as i32 - radius as i32);
+            dx * dx + dy * dy <= (radius * radius + radius) as i32
+        });
+        body.shatter_speed = Some(9.0);
+        body
+    }
+
+    pub fn plank(center: (f32, f32), length: usize, rng: &mut StdRng) -> Body {
+        Body::new(&CELL_WOOD, length, 2, center, rng, |_, _| true)
+    }
+
+    pub fn position(&self) -> (f32, f32) {
+        self.position
+    }
+
+    // Changes the velocity by the given amount, lighter bodies are pushed further
+    pub fn push(&mut self, velocity: (f32, f32)) {
+        let density = self.density();
+        self.velocity.0 += velocity.0 / density;
+        self.velocity.1 += velocity.1 / density;
+    }
+
+    pub fn mass(&self) -> usize {
+        self.shape.iter().filter(|cell| cell.is_some()).count()
+    }
+
+    fn radius(&self) -> f32 {
+        (self.width as f32).hypot(self.height as f32) / 2.0
+    }
+
+    fn density(&self) -> f32 {
+        CellType::get_density(self.material, &Materials::default())
+    }
+
+    // Cells covered by the body at the given pose, as grid coordinates and index in the shape.
+    // Every grid cell is mapped back into the shape, so a turned body has no holes.
+    fn footprint(&self, position: (f32, f32), angle: f32) -> Vec<(i32, i32, usize)> {
+        let mut cells = vec![];
+        let (sin, cos) = angle.sin_cos();
+        let half = ((self.width - 1) as f32 / 2.0, (self.height - 1) as f32 / 2.0);
+        let radius = self.radius().ceil() as i32 + 1;
+        let center = (position.0.round() as i32, position.1.round() as i32);
+
+        for y in (center.1 - radius)..=(center.1 + radius) {
+            for x in (center.0 - radius)..=(center.0 + radius) {
+                let (dx, dy) = (x as f32 - position.0, y as f32 - position.1);
+                // Rounds halves up, so even sized bodies keep all of their cells when not turned
+                let local = ((dx * cos + dy * sin + half.0 + 0.5).floor(), (-dx * sin + dy * cos + half.1 + 0.5).floor());
+                if local.0 < 0.0 || local.1 < 0.0 || local.0 >= self.width as f32 || local.1 >= self.height as f32 {
+                    continue;
+                }
+                let index = local.1 as usize * self.width + local.0 as usize;
+                if self.shape[index].is_some() {
+                    cells.append(&mut vec![(x, y, index)]);
+                }
+            }
+        }
+        cells
+    }
+
+    fn probe(&self, grid: &Grid, position: (f32, f32), angle: f32) -> Probe {
+        let mut probe = Probe::default();
+        for (x, y, _) in self.footprint(position, angle) {
+            if x < 0 || y < 0 || x >= GRID_WIDTH as i32 || y >= GRID_WIDTH as i32 {
+                probe.blocking.append(&mut vec![(x, y)]);
+                continue;
+            }
+            let cell_type = grid.cells.material(y as usize * GRID_WIDTH + x as usize);
+            if blocks_bodies(cell_type, &grid.materials) {
+                probe.blocking.append(&mut vec![(x, y)]);
+            } else if CellType::is_movable_solid(cell_type, &grid.materials) {
+                probe.loose.append(&mut vec![(x, y)]);
+                probe.loose_mass += CellType::get_density(cell_type, &grid.materials);
+            }
+        }
+        probe
+    }
+
+    // Moves and rotates the body for one tick. Returns false once the body is gone.
+    pub fn step(&mut self, grid: &mut Grid) -> bool {
+        self.lift(grid);
+        if self.mass() == 0 {
+            return false;
+        }
+
+        // Liquids around the body push it against gravity and slow it down
+        let surrounding_density = self.surrounding_density(grid);
+        let weight = 1.0 - surrounding_density / self.density();
+        self.velocity = (self.velocity.0 + grid.gravity.0 * weight, self.velocity.1 + grid.gravity.1 * weight);
+        let drag = 1.0 - LIQUID_DRAG * surrounding_density.min(1.0);
+        self.velocity = (self.velocity.0 * drag, self.velocity.1 * drag);
+        self.angular_velocity *= ANGULAR_DAMPING;
+
+        // Sub-steps of at most one cell, so fast bodies don't tunnel through thin walls
+        let distance = self.velocity.0.abs().max(self.velocity.1.abs()).max(self.angular_velocity.abs() * self.radius());
+        let steps = distance.ceil().max(1.0) as usize;
+        let mut shattered = false;
+        for _ in 0..steps {
+            let turn = self.angular_velocity / steps as f32;
+            if turn != 0.0 {
+                if self.probe(grid, self.position, self.angle + turn).blocking.is_empty() {
+                    self.angle += turn;
+                } else {
+                    self.angular_velocity *= -RESTITUTION;
+                }
+            }
+
+            if let Some(contacts) = self.advance(grid, (0.0, self.velocity.1 / steps as f32)) {
+                let speed = self.velocity.1.abs();
+                if self.shatter_speed.is_some_and(|shatter_speed| speed >= shatter_speed) {
+                    shattered = true;
+                    break;
+                }
+                // Landing on something, when gravity points up or down
+                if self.velocity.1 * grid.gravity.1 > 0.0 {
+                    self.tip(&contacts, grid.gravity.1.signum());
+                }
+                self.velocity.1 = if speed > BOUNCE_SPEED { -self.velocity.1 * RESTITUTION } else { 0.0 };
+                self.velocity.0 *= FRICTION;
+            }
+
+            if self.advance(grid, (self.velocity.0 / steps as f32, 0.0)).is_some() {
+                if self.shatter_speed.is_some_and(|shatter_speed| self.velocity.0.abs() >= shatter_speed) {
+                    shattered = true;
+                    break;
+                }
+                self.velocity.0 *= -RESTITUTION;
+            }
+        }
+
+        if shattered {
+            self.shatter(grid);
+            return false;
+        }
+        self.stamp(grid);
+        true
+    }
+
+    // Moves the body by a fraction of a cell along one axis, slowed down by the loose cells it
+    // pushes into. Returns the cells that stopped it.
+    fn advance(&mut self, grid: &Grid, delta: (f32, f32)) -> Option<Vec<(i32, i32)>> {
+        if delta == (0.0, 0.0) {
+            return None;
+        }
+        let position = (self.position.0 + delta.0, self.position.1 + delta.1);
+        let probe = self.probe(grid, position, self.angle);
+        if !probe.blocking.is_empty() {
+            return Some(probe.blocking);
+        }
+
+        if !probe.loose.is_empty() {
+            let loss = probe.loose_mass * LOOSE_RESISTANCE / (self.mass() as f32 * self.density());
+            let velocity = if delta.0 != 0.0 { &mut self.velocity.0 } else { &mut self.velocity.1 };
+            if velocity.abs() < PLOUGH_SPEED {
+                return Some(probe.loose);
+            }
+            *velocity = velocity.signum() * (velocity.abs() - loss).max(0.0);
+        }
+
+        self.position = position;
+        None
+    }
+
+    // Resting on cells only on one side of the center makes the body tip over towards the other side
+    fn tip(&mut self, contacts: &[(i32, i32)], down: f32) {
+        let offsets = contacts.iter().map(|(x, _)| *x as f32 - self.position.0);
+        let min = offsets.clone().fold(f32::MAX, f32::min);
+        let max = offsets.fold(f32::MIN, f32::max);
+        if max < -0.5 {
+            self.angular_velocity += TIP_ACCELERATION * down;
+        } else if min > 0.5 {
+            self.angular_velocity -= TIP_ACCELERATION * down;
+        } else {
+            self.angular_velocity *= 0.5;
+        }
+    }
+
+    // Mean density of what surrounds the body, solid neighbours count as nothing
+    fn surrounding_density(&self, grid: &Grid) -> f32 {
+        let footprint: HashSet<(i32, i32)> = self.footprint(self.position, self.angle).iter().map(|(x, y, _)| (*x, *y)).collect();
+        // Ordered, so the densities are always added up in the same order and the same seed floats the same
+        let mut surroundings = BTreeSet::new();
+        for (x, y) in &footprint {
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let neighbour = (x + dx, y + dy);
+                if !footprint.contains(&neighbour) {
+                    surroundings.insert(neighbour);
+                }
+            }
+        }
+
+        let mut density = 0.0;
+        for (x, y) in &surroundings {
+            if *x < 0 || *y < 0 || *x >= GRID_WIDTH as i32 || *y >= GRID_WIDTH as i32 {
+                continue;
+            }
+            let cell_type = grid.cells.material(*y as usize * GRID_WIDTH + *x as usize);
+            if !CellType::is_solid(cell_type, &grid.materials) {
+                density += CellType::get_density(cell_type, &grid.materials);
+            }
+        }
+        density / surroundings.len().max(1) as f32
+    }
+
+    // Takes the body's cells out of the grid. Cells that aren't there anymore were erased.
+    fn lift(&mut self, grid: &mut Grid) {
+        self.vacated.clear();
+        for (pos, index) in std::mem::take(&mut self.stamped) {
+            if grid.cells.material(pos).eq(self.material) {
+                grid.cells.set(pos, Cell::new(&CELL_AIR, &grid.materials));
+                self.vacated.append(&mut vec![((pos % GRID_WIDTH) as i32, (pos / GRID_WIDTH) as i32)]);
+            } else {
+                self.shape[index] = None;
+            }
+        }
+    }
+
+    pub fn stamp(&mut self, grid: &mut Grid) {
+        let (material, materials) = (self.material, grid.materials.clone());
+        self.stamped = self.place_cells(grid, |color| {
+            let mut cell = Cell::new(material, &materials);
+            cell.color = color;
+            cell
+        });
+    }
+
+    // Replaces the body with loose debris flying away from its center
+    fn shatter(&mut self, grid: &mut Grid) {
+        let (position, velocity, materials) = (self.position, self.velocity, grid.materials.clone());
+        let cells = self.place_cells(grid, |color| {
+            let mut cell = Cell::new(&CELL_DEBRIS, &materials);
+            cell.color = color;
+            cell
+        });
+        for (pos, _) in cells {
+            let (dx, dy) = ((pos % GRID_WIDTH) as f32 - position.0, (pos / GRID_WIDTH) as f32 - position.1);
+            grid.cells.velocity[pos] = (velocity.0 * RESTITUTION + dx * SHATTER_SPREAD, -velocity.1.abs() * RESTITUTION + dy * SHATTER_SPREAD);
+        }
+    }
+
+    // Puts a cell made from the shape's color on every free cell of the footprint, pushing loose
+    // cells and liquids out of the way
+    fn place_cells(&self, grid: &mut Grid, make_cell: impl Fn([u8;4]) -> Cell) -> Vec<(usize, usize)> {
+        let footprint = self.footprint(self.position, self.angle);
+        let covered: HashSet<(i32, i32)> = footprint.iter().map(|(x, y, _)| (*x, *y)).collect();
+
+        let mut placed = vec![];
+        for (x, y, index) in footprint {
+            if x < 0 || y < 0 || x >= GRID_WIDTH as i32 || y >= GRID_WIDTH as i32 {
+                continue;
+            }
+            let pos = y as usize * GRID_WIDTH + x as usize;
+            let cell_type = grid.cells.material(pos);
+            if blocks_bodies(cell_type, &grid.materials) {
+                continue;
+            }
+            // A cell with nowhere to go stays, the body just isn't drawn over it
+            if !cell_type.eq(&CELL_AIR) && !displace(grid, (x, y), &covered, &self.vacated) {
+                continue;
+            }
+            grid.cells.set(pos, make_cell(self.shape[index].unwrap()));
+            placed.append(&mut vec![(pos, index)]);
+        }
+        placed
+    }
+}
+
+// Cells bodies can't move into
+fn blocks_bodies(cell_type: &CellType, materials: &Materials) -> bool {
+    CellType::is_solid(cell_type, materials) && !CellType::is_movable_solid(cell_type, materials)
+}
+
+// Moves the cell into the nearest cell the body left behind, so it swaps places with the body. Otherwise
+// to the nearest free cell, or the first free cell above it if there is none close by. Returns
+// false and leaves the cell where it is if there is no free cell at all.
+fn displace(grid: &mut Grid, from: (i32, i32), covered: &HashSet<(i32, i32)>, vacated: &[(i32, i32)]) -> bool {
+    let is_free = |grid: &Grid, (x, y): (i32, i32)| {
+        x >= 0 && y >= 0 && x < GRID_WIDTH as i32 && y < GRID_WIDTH as i32
+            && !covered.contains(&(x, y))
+            && grid.cells.is_air(y as usize * GRID_WIDTH + x as usize)
+    };
+
+    let mut target = vacated
+        .iter()
+        .filter(|cell| is_free(grid, **cell))
+        .min_by_key(|(x, y)| (x - from.0).pow(2) + (y - from.1).pow(2))
+        .copied();
+    'search: for distance in 1..=MAX_DISPLACEMENT {
+        if target.is_some() {
+            break;
+        }
+        for dy in -distance..=distance {
+            for dx in -distance..=distance {
+                if dx.abs().max(dy.abs()) == distance && is_free(grid, (from.0 + dx, from.1 + dy)) {
+                    target = Some((from.0 + dx, from.1 + dy));
+                    break 'search;
+                }
+            }
+        }
+    }
+    if target.is_none() {
+        for y in (0..from.1).rev() {
+            if is_free(grid, (from.0, y)) {
+                target = Some((from.0, y));
+                break;
+            }
+        }
+    }
+
+    let Some((x, y)) = target else {
+        return false;
+    };
+    let from = from.1 as usize * GRID_WIDTH + from.0 as usize;
+    let mut cell = grid.cells.get(from);
+    // Wake it up, it has to find a new place to rest
+    cell.velocity = (0.0, 0.0);
+    cell.remainder = (0.0, 0.0);
+    cell.free_falling = 0;
+    cell.grounded = false;
+    cell.moved = true;
+    grid.cells.set(y as usize * GRID_WIDTH + x as usize, cell);
+    grid.cells.set(from, Cell::new(&CELL_AIR, &grid.materials));
+    true
+}
+
+impl Grid {
+    pub fn add_body(&mut self, mut body: Body) {
+        body.stamp(self);
+        self.bodies.append(&mut vec![body]);
+    }
+
+    pub fn step_bodies(&mut self) {
+        let mut bodies = std::mem::take(&mut self.bodies);
+        bodies.retain_mut(|body| body.step(self));
+        self.bodies = bodies;
+    }
+}
@@ -0,0 +1,74 @@
+// Plain text scene format, used both to load starting grids and to save the current one.
+// Every line is a row of the grid and every character a cell, using the material symbols
+// from CellType::get_symbol. Short rows and missing rows are filled with air.
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::scripting::Scripts;
+use crate::{CellType, Grid, GRID_WIDTH};
+
+#[derive(Debug)]
+pub enum SceneError {
+    UnknownSymbol { line: usize, column: usize, symbol: char },
+    TooLarge { width: usize, height: usize }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::UnknownSymbol { line, column, symbol } => {
+                write!(f, "unknown material symbol '{symbol}' at line {line}, column {column}")
+            }
+            SceneError::TooLarge { width, height } => {
+                write!(f, "scene is {width}x{height} but the grid is only {GRID_WIDTH}x{GRID_WIDTH}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl Grid {
+    // The scripts come first, the scene may use the materials they define
+    pub fn from_scene(text: &str, seed: u64, scripts: Option<Rc<Scripts>>) -> Result<Grid, SceneError> {
+        let rows: Vec<&str> = text.lines().collect();
+        let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+        if width > GRID_WIDTH || rows.len() > GRID_WIDTH {
+            return Err(SceneError::TooLarge { width, height: rows.len() });
+        }
+
+        let mut grid = Grid::new(seed);
+        if let Some(scripts) = scripts {
+            grid.set_scripts(scripts);
+        }
+        for (y, row) in rows.iter().enumerate() {
+            for (x, symbol) in row.chars().enumerate() {
+                let cell_type = CellType::from_symbol(symbol, &grid.materials)
+                    .ok_or(SceneError::UnknownSymbol { line: y + 1, column: x + 1, symbol })?;
+                grid.place(y * GRID_WIDTH + x, cell_type);
+            }
+        }
+
+        Ok(grid)
+    }
+
+    // Trailing air is left out, loading the scene back fills it in again
+    pub fn to_scene(&self) -> String {
+        let materials: Vec<usize> = self.cells.material_indices().collect();
+        let mut rows: Vec<String> = materials
+            .chunks_exact(GRID_WIDTH)
+            .map(|row| {
+                let line: String = row.iter().map(|index| CellType::get_symbol(CellType::from_index(*index), &self.materials)).collect();
+                line.trim_end_matches(CellType::get_symbol(&CellType::Air, &self.materials)).to_string()
+            })
+            .collect();
+        while rows.last().is_some_and(|row| row.is_empty()) {
+            rows.pop();
+        }
+
+        let mut text = rows.join("\n");
+        text.push('\n');
+        text
+    }
+}
@@ -0,0 +1,551 @@
+// Custom materials and their per-tick rules, defined in Rhai scripts without recompiling.
+//
+// A script declares materials at its top level:
+//
+//     material("lava", #{
+//         symbol: "L",
+//         colors: [[207, 53, 16], [230, 90, 20]],
+//         movable: false,
+//         tick: "lava_tick"
+//     });
+//
+//     fn lava_tick(cell) {
+//         if cell.neighbour(0, 1) == "air" {
+//             cell.move_by(0, 1);
+//         }
+//         if cell.neighbour(0, -1) == "water" {
+//             cell.set_material("stone");
+//         }
+//         cell.temperature += 0.5;
+//     }
+//
+// Material properties:
+//     symbol               character used in scene files (required)
+//     colors / color       palette as [r, g, b] arrays, or a single color
+//     color_noise          random brightness offset of new cells
+//     solid                other cells can't move into it (default true)
+//     movable              falls and piles up like sand, implies solid (default false)
+//     liquid               flows and evens out its level like water, implies not solid (default false)
+//     gas                  spreads out like CO2, implies not solid (default false)
+//     roll_speed           sideways speed when sliding down a pile (default 1.5)
+//     inertial_resistance  chance to resist being woken up by neighbours (default 0.3)
+//     temperature          temperature of new cells (default 20)
+//     density              relative to water, decides what rigid bodies float in (default 1)
+//     restitution          share of its speed it keeps when bouncing off something, 0 to 1 (default 0.1)
+//     drag                 share of its speed lost each tick in the air, a number or [sideways, falling]
+//                          (default [0.2, 0.01])
+//     terminal_velocity    fastest it falls, in cells per tick (default 8)
+//     emits                name of a material put into neighbouring air, defined before this one
+//     emit_rate            chance to put out a cell each tick (default 0.5)
+//     drain                deletes loose cells touching it (default false)
+//     lifetime             ticks a cell lasts, a number or [shortest, longest] to pick from at random
+//     decays_into          name of the material it turns into at the end of its lifetime (default air)
+//     fade                 darkens with age until it decays (default false)
+//     tick                 name of the function called for every cell of the material each tick
+//
+// Reactions between touching materials are declared anywhere in the script:
+//
+//     reaction(#{
+//         reactants: ["lava", "water"],
+//         products: ["stone", "steam"],
+//         probability: 0.5,
+//         min_temperature: 500.0
+//     });
+//
+// Reaction properties:
+//     reactants            the two touching materials, every cell of the first one checks its neighbours
+//     products             what the two cells turn into, "air" to remove them
+//     probability          chance to react per tick and pair of cells (default 1)
+//     min_temperature      the first reactant must be at least this hot
+//     max_temperature      and at most this hot
+//
+// The tick function gets a handle to the cell:
+//     cell.material                  material name
+//     cell.temperature               can be changed
+//     cell.age                       ticks since the cell was created
+//     cell.x, cell.y                 position in the grid
+//     cell.velocity_x, velocity_y
+//     cell.neighbour(dx, dy)         material name of a neighbour, "none" outside of the grid
+//     cell.neighbour_temperature(dx, dy)
+//     cell.move_by(dx, dy)           requests a swap with a non-solid neighbour, returns if it was possible
+//     cell.set_material(name)        turns the cell into another material
+//     cell.random()                  random number in 0.0..1.0
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use log::error;
+use rand::Rng;
+use rand::rngs::StdRng;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, ParseError, AST};
+
+use crate::lifetime::Lifetime;
+use crate::reaction::Reaction;
+use crate::{Cell, CellType, Changes, Grid, AMBIENT_TEMPERATURE, CELL_AIR, GRID_WIDTH};
+
+const MAX_OPERATIONS_PER_CALL: u64 = 100_000; // For every call of a tick function and for loading
+const DEFAULT_ROLL_SPEED: f32 = 1.5;
+const DEFAULT_INERTIAL_RESISTANCE: f64 = 0.3;
+const DEFAULT_RESTITUTION: f32 = 0.1;
+const DEFAULT_DRAG: (f32, f32) = (0.2, 0.01);
+const DEFAULT_TERMINAL_VELOCITY: f32 = 8.0;
+
+pub struct ScriptMaterial {
+    pub cell_type: &'static CellType,
+    pub name: String,
+    pub symbol: char,
+    pub palette: Vec<[u8;4]>,
+    pub color_noise: i16,
+    pub solid: bool,
+    pub movable: bool,
+    pub liquid: bool,
+    pub gas: bool,
+    pub roll_speed: f32,
+    pub inertial_resistance: f64,
+    pub temperature: f32,
+    pub density: f32,
+    pub restitution: f32,
+    pub drag: (f32, f32),
+    pub terminal_velocity: f32,
+    pub emits: Option<(&'static CellType, f64)>,
+    pub drain: bool,
+    pub lifetime: Option<Lifetime>,
+    pub tick: Option<String>
+}
+
+// The materials defined by the scripts a grid was loaded with, cells store them as CellType::Script
+// with their position in here. Every grid has its own, so the same id can mean different materials
+// in different grids. Defining a material again under the same name replaces its properties but
+// keeps its id.
+#[derive(Default)]
+pub struct Materials(Vec<ScriptMaterial>);
+
+impl Materials {
+    pub fn get(&self, id: u8) -> &ScriptMaterial {
+        &self.0[id as usize]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ScriptMaterial> {
+        self.0.iter()
+    }
+
+    fn register(&mut self, mut material: ScriptMaterial) -> Result<(), ScriptError> {
+        if CellType::BUILTIN.iter().any(|cell_type| CellType::get_name(cell_type, self) == material.name) {
+            return Err(ScriptError::Definition(format!("material {} is built in", material.name)));
+        }
+        let existing = self.0.iter().position(|m| m.name == material.name);
+        if CellType::BUILTIN.iter().any(|cell_type| CellType::get_symbol(cell_type, self) == material.symbol)
+            || self.0.iter().enumerate().any(|(i, m)| m.symbol == material.symbol && Some(i) != existing) {
+            return Err(ScriptError::Definition(format!("symbol '{}' of {} is already used", material.symbol, material.name)));
+        }
+
+        let id = existing.unwrap_or(self.0.len());
+        if id > u8::MAX as usize {
+            return Err(ScriptError::Definition("too many script materials".into()));
+        }
+        material.cell_type = CellType::script(id as u8);
+
+        match existing {
+            Some(id) => { self.0[id] = material; }
+            None => { self.0.append(&mut vec![material]); }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(PathBuf, io::Error),
+    Parse(ParseError),
+    Eval(Box<EvalAltResult>),
+    Definition(String)
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Io(path, _) => { write!(f, "couldn't read script {}", path.display()) }
+            ScriptError::Parse(_) => { write!(f, "couldn't parse script") }
+            ScriptError::Eval(_) => { write!(f, "script failed") }
+            ScriptError::Definition(message) => { write!(f, "invalid material: {message}") }
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScriptError::Io(_, err) => { Some(err) }
+            ScriptError::Parse(err) => { Some(err) }
+            ScriptError::Eval(err) => { Some(err.as_ref()) }
+            ScriptError::Definition(_) => { None }
+        }
+    }
+}
+
+pub struct Scripts {
+    engine: Engine,
+    ast: AST,
+    pub materials: Rc<Materials>,
+    pub reactions: Vec<Reaction>,
+    // Only the first runtime error is logged, it would otherwise repeat for every cell each tick
+    reported_error: std::cell::Cell<bool>
+}
+
+impl Scripts {
+    pub fn load_file(path: &Path) -> Result<Scripts, ScriptError> {
+        let source = fs::read_to_string(path).map_err(|err| ScriptError::Io(path.to_path_buf(), err))?;
+        Scripts::load(&source)
+    }
+
+    // Runs the top level of the script and registers the materials it defines
+    pub fn load(source: &str) -> Result<Scripts, ScriptError> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS_PER_CALL);
+
+        let definitions: Rc<RefCell<Vec<(String, Map)>>> = Rc::default();
+        let collected = definitions.clone();
+        engine.register_fn("material", move |name: &str, properties: Map| {
+            collected.borrow_mut().push((name.to_string(), properties));
+        });
+        let reaction_definitions: Rc<RefCell<Vec<Map>>> = Rc::default();
+        let collected = reaction_definitions.clone();
+        engine.register_fn("reaction", move |properties: Map| {
+            collected.borrow_mut().push(properties);
+        });
+        register_cell_api(&mut engine);
+
+        let ast = engine.compile(source).map_err(ScriptError::Parse)?;
+        engine.run_ast(&ast).map_err(ScriptError::Eval)?;
+
+        let mut materials = Materials::default();
+        for (name, properties) in definitions.take() {
+            let material = parse_material(&name, &properties, &materials)?;
+            materials.register(material)?;
+        }
+        // After the materials, reactions can use any of them
+        let mut reactions = vec![];
+        for properties in reaction_definitions.take() {
+            reactions.append(&mut vec![parse_reaction(&properties, &materials)?]);
+        }
+
+        Ok(Scripts {
+            engine,
+            ast,
+            materials: Rc::new(materials),
+            reactions,
+            reported_error: std::cell::Cell::new(false)
+        })
+    }
+
+    pub fn tick(&self, function: &str, grid: &mut Grid, pos: usize, changes: &mut Changes, rng: &mut StdRng) {
+        let mut neighbours = [None; 8];
+        for (i, (p, n)) in Cell::get_neighbours(grid, pos).iter().enumerate() {
+            neighbours[i] = n.map(|n| (*p, n, grid.cells.temperature[*p]));
+        }
+        let handle = ScriptCell(Rc::new(RefCell::new(ScriptCellState {
+            pos,
+            material: grid.cells.material(pos),
+            materials: grid.materials.clone(),
+            temperature: grid.cells.temperature[pos],
+            age: grid.cells.age(pos),
+            velocity: grid.cells.velocity[pos],
+            neighbours,
+            movement: None,
+            random_state: rng.random::<u64>() | 1
+        })));
+
+        let result = self.engine.call_fn::<Dynamic>(&mut rhai::Scope::new(), &self.ast, function, (handle.clone(),));
+        if let Err(err) = result {
+            if !self.reported_error.replace(true) {
+                error!("script function {function}() failed: {err}");
+            }
+            return;
+        }
+
+        let state = handle.0.borrow();
+        if let Some(target) = state.movement {
+            changes.pos.append(&mut vec![(pos, target)]);
+        }
+        // A new material gets its own color but keeps the temperature
+        if !state.material.eq(grid.cells.material(pos)) {
+            let cell = Cell::new_at(state.material, pos, &grid.materials, rng);
+            grid.cells.set(pos, cell);
+        }
+        grid.cells.temperature[pos] = state.temperature;
+    }
+}
+
+// Materials it emits or decays into have to be defined before it
+fn parse_material(name: &str, properties: &Map, materials: &Materials) -> Result<ScriptMaterial, ScriptError> {
+    let invalid = |property: &str| ScriptError::Definition(format!("{name}: invalid {property}"));
+
+    let symbol = match properties.get("symbol") {
+        Some(value) => {
+            let symbol = value.clone().into_string().map_err(|_| invalid("symbol"))?;
+            let mut chars = symbol.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => { c }
+                _ => { return Err(invalid("symbol")); }
+            }
+        }
+        None => { return Err(ScriptError::Definition(format!("{name}: missing symbol"))); }
+    };
+
+    let mut palette = vec![];
+    if let Some(value) = properties.get("colors") {
+        let colors = value.clone().try_cast::<Array>().ok_or_else(|| invalid("colors"))?;
+        for color in colors {
+            palette.append(&mut vec![parse_color(&color).ok_or_else(|| invalid("colors"))?]);
+        }
+    }
+    if let Some(value) = properties.get("color") {
+        palette.append(&mut vec![parse_color(value).ok_or_else(|| invalid("color"))?]);
+    }
+    if palette.is_empty() {
+        palette.append(&mut vec![[255, 255, 255, 255]]);
+    }
+
+    let get_bool = |property: &str, default: bool| match properties.get(property) {
+        Some(value) => { value.as_bool().map_err(|_| invalid(property)) }
+        None => { Ok(default) }
+    };
+    let get_float = |property: &str, default: f64| match properties.get(property) {
+        Some(value) => { as_number(value).ok_or_else(|| invalid(property)) }
+        None => { Ok(default) }
+    };
+
+    let movable = get_bool("movable", false)?;
+    let liquid = !movable && get_bool("liquid", false)?;
+    let gas = !movable && !liquid && get_bool("gas", false)?;
+    let tick = match properties.get("tick") {
+        Some(value) => {
+            if let Some(function) = value.clone().try_cast::<rhai::FnPtr>() {
+                Some(function.fn_name().to_string())
+            } else {
+                Some(value.clone().into_string().map_err(|_| invalid("tick"))?)
+            }
+        }
+        None => { None }
+    };
+    let emits = match properties.get("emits") {
+        Some(value) => {
+            let emitted = value.clone().into_string().map_err(|_| invalid("emits"))?;
+            let cell_type = CellType::find(&emitted, materials).ok_or_else(|| invalid("emits"))?;
+            Some((cell_type, get_float("emit_rate", 0.5)?.clamp(0.0, 1.0)))
+        }
+        None => { None }
+    };
+
+    let drag = match properties.get("drag") {
+        Some(value) => {
+            let drag = match value.clone().try_cast::<Array>() {
+                Some(axes) => {
+                    match axes.iter().map(as_number).collect::<Option<Vec<f64>>>().as_deref() {
+                        Some([sideways, falling]) => { (*sideways, *falling) }
+                        _ => { return Err(invalid("drag")); }
+                    }
+                }
+                None => {
+                    let drag = as_number(value).ok_or_else(|| invalid("drag"))?;
+                    (drag, drag)
+                }
+            };
+            (drag.0.clamp(0.0, 1.0) as f32, drag.1.clamp(0.0, 1.0) as f32)
+        }
+        None => { DEFAULT_DRAG }
+    };
+
+    let lifetime = match properties.get("lifetime") {
+        Some(value) => {
+            let ticks = match value.clone().try_cast::<Array>() {
+                Some(range) => {
+                    match range.iter().map(|ticks| ticks.as_int().ok()).collect::<Option<Vec<i64>>>().as_deref() {
+                        Some([shortest, longest]) if shortest <= longest => { (*shortest, *longest) }
+                        _ => { return Err(invalid("lifetime")); }
+                    }
+                }
+                None => {
+                    let ticks = value.as_int().map_err(|_| invalid("lifetime"))?;
+                    (ticks, ticks)
+                }
+            };
+            let decays_into = match properties.get("decays_into") {
+                Some(value) => { value.clone().into_string().ok().and_then(|name| CellType::find(&name, materials)).ok_or_else(|| invalid("decays_into"))? }
+                None => { &CELL_AIR }
+            };
+            let clamp = |ticks: i64| ticks.clamp(1, u16::MAX as i64) as u16;
+            Some(Lifetime { ticks: (clamp(ticks.0), clamp(ticks.1)), decays_into, fades: get_bool("fade", false)? })
+        }
+        None => { None }
+    };
+
+    Ok(ScriptMaterial {
+        cell_type: &CellType::Air, // Set when registered
+        name: name.to_uppercase(),
+        symbol,
+        palette,
+        color_noise: get_float("color_noise", 0.0)? as i16,
+        solid: movable || (!liquid && !gas && get_bool("solid", true)?),
+        movable,
+        liquid,
+        gas,
+        roll_speed: get_float("roll_speed", DEFAULT_ROLL_SPEED as f64)? as f32,
+        inertial_resistance: get_float("inertial_resistance", DEFAULT_INERTIAL_RESISTANCE)?.clamp(0.0, 1.0),
+        temperature: get_float("temperature", AMBIENT_TEMPERATURE as f64)? as f32,
+        density: get_float("density", 1.0)?.max(0.0) as f32,
+        restitution: get_float("restitution", DEFAULT_RESTITUTION as f64)?.clamp(0.0, 1.0) as f32,
+        drag,
+        terminal_velocity: get_float("terminal_velocity", DEFAULT_TERMINAL_VELOCITY as f64)?.max(0.0) as f32,
+        emits,
+        drain: get_bool("drain", false)?,
+        lifetime,
+        tick
+    })
+}
+
+fn parse_reaction(properties: &Map, materials: &Materials) -> Result<Reaction, ScriptError> {
+    let invalid = |property: &str| ScriptError::Definition(format!("reaction: invalid {property}"));
+    let get_pair = |property: &str| -> Result<(&'static CellType, &'static CellType), ScriptError> {
+        let names = properties.get(property).and_then(|value| value.clone().try_cast::<Array>()).ok_or_else(|| invalid(property))?;
+        let materials: Option<Vec<&'static CellType>> = names
+            .iter()
+            .map(|name| name.clone().into_string().ok().and_then(|name| CellType::find(&name, materials)))
+            .collect();
+        match materials.as_deref() {
+            Some([first, second]) => { Ok((first, second)) }
+            _ => { Err(invalid(property)) }
+        }
+    };
+    let get_temperature = |property: &str| match properties.get(property) {
+        Some(value) => { as_number(value).map(|t| Some(t as f32)).ok_or_else(|| invalid(property)) }
+        None => { Ok(None) }
+    };
+
+    let probability = match properties.get("probability") {
+        Some(value) => { as_number(value).ok_or_else(|| invalid("probability"))?.clamp(0.0, 1.0) }
+        None => { 1.0 }
+    };
+
+    Ok(Reaction {
+        reactants: get_pair("reactants")?,
+        products: get_pair("products")?,
+        probability,
+        min_temperature: get_temperature("min_temperature")?,
+        max_temperature: get_temperature("max_temperature")?
+    })
+}
+
+// Scripts may write whole numbers without a decimal point
+fn as_number(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|i| i as f64))
+}
+
+fn parse_color(value: &Dynamic) -> Option<[u8;4]> {
+    let channels = value.clone().try_cast::<Array>()?;
+    if channels.len() != 3 && channels.len() != 4 {
+        return None;
+    }
+    let mut color = [255u8; 4];
+    for (i, channel) in channels.iter().enumerate() {
+        color[i] = channel.as_int().ok()?.clamp(0, 255) as u8;
+    }
+    Some(color)
+}
+
+// Handle to the cell a tick function runs for. Clones share the state, so changes made
+// by the script are visible after the call.
+#[derive(Clone)]
+struct ScriptCell(Rc<RefCell<ScriptCellState>>);
+
+struct ScriptCellState {
+    pos: usize,
+    material: &'static CellType,
+    materials: Rc<Materials>,
+    temperature: f32,
+    age: u16,
+    velocity: (f32, f32),
+    neighbours: [Option<(usize, &'static CellType, f32)>; 8], // In the order of Cell::get_neighbours
+    movement: Option<usize>,
+    random_state: u64
+}
+
+impl ScriptCellState {
+    fn neighbour(&self, dx: i64, dy: i64) -> Option<(usize, &'static CellType, f32)> {
+        let index = match (dx, dy) {
+            (-1, -1) => { 0 }
+            (0, -1) => { 1 }
+            (1, -1) => { 2 }
+            (-1, 0) => { 3 }
+            (1, 0) => { 4 }
+            (-1, 1) => { 5 }
+            (0, 1) => { 6 }
+            (1, 1) => { 7 }
+            _ => { return None; }
+        };
+        self.neighbours[index]
+    }
+}
+
+fn script_name(cell_type: &CellType, materials: &Materials) -> String {
+    CellType::get_name(cell_type, materials).to_lowercase()
+}
+
+fn register_cell_api(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptCell>("Cell")
+        .register_get("material", |cell: &mut ScriptCell| {
+            let state = cell.0.borrow();
+            script_name(state.material, &state.materials)
+        })
+        .register_get_set(
+            "temperature",
+            |cell: &mut ScriptCell| cell.0.borrow().temperature as f64,
+            |cell: &mut ScriptCell, temperature: f64| { cell.0.borrow_mut().temperature = temperature as f32; }
+        )
+        .register_get("age", |cell: &mut ScriptCell| cell.0.borrow().age as i64)
+        .register_get("x", |cell: &mut ScriptCell| (cell.0.borrow().pos % GRID_WIDTH) as i64)
+        .register_get("y", |cell: &mut ScriptCell| (cell.0.borrow().pos / GRID_WIDTH) as i64)
+        .register_get("velocity_x", |cell: &mut ScriptCell| cell.0.borrow().velocity.0 as f64)
+        .register_get("velocity_y", |cell: &mut ScriptCell| cell.0.borrow().velocity.1 as f64)
+        .register_fn("neighbour", |cell: &mut ScriptCell, dx: i64, dy: i64| {
+            let state = cell.0.borrow();
+            match state.neighbour(dx, dy) {
+                Some((_, cell_type, _)) => { script_name(cell_type, &state.materials) }
+                None => { "none".to_string() }
+            }
+        })
+        .register_fn("neighbour_temperature", |cell: &mut ScriptCell, dx: i64, dy: i64| {
+            cell.0.borrow().neighbour(dx, dy).map(|(_, _, temperature)| temperature as f64).unwrap_or(0.0)
+        })
+        .register_fn("move_by", |cell: &mut ScriptCell, dx: i64, dy: i64| {
+            let mut state = cell.0.borrow_mut();
+            match state.neighbour(dx, dy) {
+                Some((p, cell_type, _)) if !CellType::is_solid(cell_type, &state.materials) => {
+                    state.movement = Some(p);
+                    true
+                }
+                _ => { false }
+            }
+        })
+        .register_fn("set_material", |cell: &mut ScriptCell, name: &str| -> Result<(), Box<EvalAltResult>> {
+            let mut state = cell.0.borrow_mut();
+            let cell_type = CellType::find(name, &state.materials).ok_or_else(|| format!("unknown material '{name}'"))?;
+            state.material = cell_type;
+            Ok(())
+        })
+        .register_fn("random", |cell: &mut ScriptCell| {
+            // xorshift64, seeded from the grid's generator for every call of the tick function
+            let mut state = cell.0.borrow_mut();
+            let mut x = state.random_state;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            state.random_state = x;
+            (x >> 11) as f64 / (1u64 << 53) as f64
+        });
+}
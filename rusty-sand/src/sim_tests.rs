@@ -0,0 +1,1146 @@
+// Simulation tests written as small ASCII maps.
+//
+// A map is a block of rows framed by '|', using the scene symbols ('#' stone, '.' sand, '~' water,
+// ' ' air...). The map is loaded into the top-left corner of a grid and everything outside of it
+// is filled with stone, so the edges of the map behave like walls.
+
+use std::rc::Rc;
+
+use crate::boundary::Boundary;
+use crate::force_field::{Force, ForceField};
+use crate::puzzle::{Attempt, Puzzle, PuzzleError};
+use crate::rigid_body::Body;
+use crate::scene::SceneError;
+use crate::scripting::Scripts;
+use crate::terrain::Terrain;
+use crate::{Cell, CellType, Grid, CELL_SAND, CELL_STONE, GRAVITY, GRID_SIZE, GRID_WIDTH};
+
+const SEED: u64 = 1;
+
+struct Scenario {
+    grid: Grid,
+    width: usize,
+    height: usize
+}
+
+fn parse_map(map: &str) -> Vec<String> {
+    map.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let row = line
+                .strip_prefix('|')
+                .and_then(|line| line.strip_suffix('|'))
+                .unwrap_or_else(|| panic!("map row {line:?} must be framed by '|'"));
+            row.to_string()
+        })
+        .collect()
+}
+
+fn scenario(map: &str) -> Scenario {
+    scenario_with_seed(map, SEED)
+}
+
+fn scenario_with_script(script: &str, map: &str) -> Scenario {
+    scenario_with_scripts(map, SEED, Some(Rc::new(Scripts::load(script).unwrap())))
+}
+
+fn scenario_with_seed(map: &str, seed: u64) -> Scenario {
+    scenario_with_scripts(map, seed, None)
+}
+
+fn scenario_with_scripts(map: &str, seed: u64, scripts: Option<Rc<Scripts>>) -> Scenario {
+    let rows = parse_map(map);
+    let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+    let height = rows.len();
+
+    let mut grid = Grid::from_scene(&rows.join("\n"), seed, scripts).unwrap();
+    for y in 0..GRID_WIDTH {
+        for x in 0..GRID_WIDTH {
+            if x >= width || y >= height {
+                grid.place(y * GRID_WIDTH + x, &CELL_STONE);
+            }
+        }
+    }
+
+    Scenario { grid, width, height }
+}
+
+impl Scenario {
+    fn run(mut self, ticks: usize) -> Scenario {
+        for _ in 0..ticks {
+            self.grid.execute_logic();
+        }
+        self
+    }
+
+    fn cell(&self, x: usize, y: usize) -> CellType {
+        *self.grid.cells.material(y * GRID_WIDTH + x)
+    }
+
+    // Built-in or defined by the scripts of the grid
+    fn material(&self, name: &str) -> &'static CellType {
+        CellType::find(name, &self.grid.materials).unwrap()
+    }
+
+    fn to_map(&self) -> Vec<String> {
+        (0..self.height)
+            .map(|y| (0..self.width).map(|x| CellType::get_symbol(&self.cell(x, y), &self.grid.materials)).collect())
+            .collect()
+    }
+
+    fn count(&self, cell_type: CellType) -> usize {
+        self.grid.count_materials()[CellType::index(&cell_type)]
+    }
+
+    fn assert_map(&self, expected: &str) {
+        let expected = parse_map(expected);
+        let actual = self.to_map();
+        assert!(
+            actual == expected,
+            "map mismatch\nexpected:\n{}\nactual:\n{}",
+            frame_rows(&expected),
+            frame_rows(&actual)
+        );
+    }
+
+    // Every cell of the given material rests on something that isn't air
+    fn assert_settled(&self, cell_type: CellType) {
+        self.assert_settled_towards(cell_type, (0, 1));
+    }
+
+    // Same as assert_settled, for gravity pointing in the given direction
+    fn assert_settled_towards(&self, cell_type: CellType, down: (i32, i32)) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (below_x, below_y) = (x as i32 + down.0, y as i32 + down.1);
+                if below_x < 0 || below_y < 0 || below_x >= GRID_WIDTH as i32 || below_y >= GRID_WIDTH as i32 {
+                    continue;
+                }
+                if self.cell(x, y) == cell_type {
+                    assert!(
+                        self.cell(below_x as usize, below_y as usize) != CellType::Air,
+                        "{} at ({x}, {y}) is floating\n{}",
+                        CellType::get_name(&cell_type, &self.grid.materials),
+                        frame_rows(&self.to_map())
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn frame_rows(rows: &[String]) -> String {
+    rows.iter().map(|row| format!("|{row}|")).collect::<Vec<_>>().join("\n")
+}
+
+#[test]
+fn grain_falls_down_a_shaft() {
+    scenario("
+        |#.#|
+        |# #|
+        |# #|
+        |# #|
+        |# #|
+        |###|
+    ")
+    .run(20)
+    .assert_map("
+        |# #|
+        |# #|
+        |# #|
+        |# #|
+        |#.#|
+        |###|
+    ");
+}
+
+#[test]
+fn grains_stack_in_a_shaft() {
+    scenario("
+        |#.#|
+        |#.#|
+        |# #|
+        |# #|
+        |# #|
+        |###|
+    ")
+    .run(30)
+    .assert_map("
+        |# #|
+        |# #|
+        |# #|
+        |#.#|
+        |#.#|
+        |###|
+    ");
+}
+
+#[test]
+fn stone_does_not_move() {
+    let map = "
+        |          |
+        |   ##     |
+        |          |
+        |       #  |
+        |          |
+    ";
+    scenario(map).run(50).assert_map(map);
+}
+
+#[test]
+fn grain_rolls_off_a_single_stone() {
+    let map = "
+        |  .  |
+        |     |
+        |     |
+        |  #  |
+        |     |
+    ";
+    for seed in 0..8 {
+        let result = scenario_with_seed(map, seed).run(30);
+
+        assert_eq!(result.count(CellType::Sand), 1);
+        assert_ne!(result.cell(2, 2), CellType::Sand, "seed {seed}: grain balanced on the stone");
+        result.assert_settled(CellType::Sand);
+        assert!(result.to_map()[4].contains('.'), "seed {seed}: grain didn't reach the floor");
+    }
+}
+
+#[test]
+fn column_spreads_into_pile() {
+    let map = "
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+        |                     |
+        |                     |
+        |                     |
+        |                     |
+    ";
+    for seed in 0..8 {
+        let result = scenario_with_seed(map, seed).run(200);
+
+        assert_eq!(result.count(CellType::Sand), 8);
+        result.assert_settled(CellType::Sand);
+        let bottom_row = &result.to_map()[result.height - 1];
+        assert!(bottom_row.matches('.').count() > 1, "seed {seed}: sand didn't spread\n{}", frame_rows(&result.to_map()));
+    }
+}
+
+#[test]
+fn settled_pile_stays_put() {
+    let map = "
+        |     .....     |
+        |     .....     |
+        |     .....     |
+        |               |
+        |               |
+        |               |
+        |               |
+    ";
+    for seed in 0..8 {
+        let settled = scenario_with_seed(map, seed).run(300);
+        let before = settled.to_map();
+        let after = settled.run(100);
+
+        assert_eq!(after.count(CellType::Sand), 15);
+        assert_eq!(after.to_map(), before, "seed {seed}: pile kept moving");
+    }
+}
+
+#[test]
+fn script_material_moves_down_a_shaft() {
+    let script = r#"
+        material("test_drip", #{ symbol: "d", solid: false, tick: "drip" });
+        fn drip(cell) { cell.move_by(0, 1); }
+    "#;
+    scenario_with_script(script, "
+        |#d#|
+        |# #|
+        |# #|
+        |###|
+    ")
+    .run(10)
+    .assert_map("
+        |# #|
+        |# #|
+        |#d#|
+        |###|
+    ");
+}
+
+#[test]
+fn script_material_turns_into_another_material() {
+    let script = r#"
+        material("test_ember", #{ symbol: "e", temperature: 100.0, tick: "cool" });
+        fn cool(cell) {
+            cell.temperature -= 10.0;
+            if cell.temperature < 50.0 && cell.neighbour(0, 1) == "stone" {
+                cell.set_material("coal");
+            }
+        }
+    "#;
+    let result = scenario_with_script(script, "
+        |e  e|
+        |#   |
+    ");
+    let result = result.run(4);
+    assert_eq!(result.cell(0, 0), *result.material("test_ember"));
+    let result = result.run(2);
+    result.assert_map("
+        |*  e|
+        |#   |
+    ");
+}
+
+#[test]
+fn movable_script_material_piles_up() {
+    let script = r#"material("test_grain", #{ symbol: "g", movable: true });"#;
+    let result = scenario_with_script(script, "
+        |   g   |
+        |   g   |
+        |   g   |
+        |       |
+        |       |
+    ")
+    .run(100);
+
+    let grain = *result.material("test_grain");
+    assert_eq!(result.count(grain), 3);
+    result.assert_settled(grain);
+}
+
+#[test]
+fn material_without_symbol_is_rejected() {
+    assert!(Scripts::load(r#"material("test_nameless", #{ solid: false });"#).is_err());
+}
+
+// Air with the given layers of material at the bottom, for maps too tall to write out
+fn tall_map(width: usize, height: usize, layers: &[(char, usize)]) -> String {
+    let mut rows = vec![];
+    for (symbol, count) in layers.iter().rev() {
+        for _ in 0..*count {
+            rows.insert(0, format!("|{}|", symbol.to_string().repeat(width)));
+        }
+    }
+    while rows.len() < height {
+        rows.insert(0, format!("|{}|", " ".repeat(width)));
+    }
+    rows.join("\n")
+}
+
+#[test]
+fn crate_lands_in_one_piece() {
+    let mut result = scenario(&tall_map(12, 30, &[]));
+    let body = Body::wooden_crate((6.0, 3.0), 4, &mut result.grid.rng);
+    result.grid.add_body(body);
+    let result = result.run(80);
+
+    assert_eq!(result.grid.bodies.len(), 1);
+    assert_eq!(result.count(CellType::Wood), 16);
+    for row in &result.to_map()[26..30] {
+        assert_eq!(row.matches('=').count(), 4, "crate isn't on the floor\n{}", frame_rows(&result.to_map()));
+    }
+}
+
+#[test]
+fn falling_body_pushes_sand_aside() {
+    let mut result = scenario(&tall_map(16, 40, &[('.', 8)]));
+    let body = Body::boulder((8.0, 4.0), 2, &mut result.grid.rng);
+    let stone = result.count(CellType::Stone) + body.mass();
+    result.grid.add_body(body);
+    let result = result.run(150);
+
+    assert_eq!(result.count(CellType::Sand), 128);
+    assert_eq!(result.count(CellType::Stone), stone);
+    // Landed on the sand, but sank into it
+    let map = result.to_map();
+    assert!(map[33].contains('#'), "boulder didn't sink into the sand\n{}", frame_rows(&map));
+    assert!(!map[39].contains('#'), "boulder went through the sand\n{}", frame_rows(&map));
+}
+
+#[test]
+fn body_dropped_into_a_full_basin_keeps_every_cell() {
+    // The water under the crate has nowhere to go, so the crate doesn't take its place
+    let mut result = scenario(&tall_map(12, 20, &[('~', 20)]));
+    let water = result.count(CellType::Water);
+    let body = Body::wooden_crate((6.0, 10.0), 4, &mut result.grid.rng);
+    result.grid.add_body(body);
+    assert_eq!(result.count(CellType::Water), water);
+
+    let result = result.run(60);
+    assert_eq!(result.count(CellType::Water), water);
+}
+
+#[test]
+fn hard_impact_shatters_body() {
+    let mut result = scenario(&tall_map(12, 40, &[]));
+    let mut body = Body::wooden_crate((6.0, 3.0), 4, &mut result.grid.rng);
+    body.shatter_speed = Some(3.0);
+    result.grid.add_body(body);
+    let result = result.run(100);
+
+    assert!(result.grid.bodies.is_empty());
+    assert_eq!(result.count(CellType::Wood), 0);
+    assert_eq!(result.count(CellType::Debris), 16);
+    result.assert_settled(CellType::Debris);
+}
+
+#[test]
+fn debris_falls_like_sand() {
+    let mut result = scenario(&tall_map(10, 20, &[]));
+    result.grid.place(2 * GRID_WIDTH + 5, &CellType::Debris);
+    result.grid.cells.velocity[2 * GRID_WIDTH + 5] = (1.0, -2.0);
+    let result = result.run(60);
+
+    assert_eq!(result.count(CellType::Debris), 1);
+    assert!((0..10).any(|x| result.cell(x, 19) == CellType::Debris), "debris didn't land\n{}", frame_rows(&result.to_map()));
+}
+
+#[test]
+fn explosion_clears_crater_and_flings_sand() {
+    let mut result = scenario(&tall_map(30, 30, &[('.', 6)]));
+    result.grid.explode((15.0, 24.0), 10.0, 6.0);
+    assert_eq!(result.cell(15, 24), CellType::Air);
+    assert_eq!(result.cell(15, 27), CellType::Air);
+
+    let sand = result.count(CellType::Sand);
+    let result = result.run(4);
+    let map = result.to_map();
+    assert!(map[..24].iter().any(|row| row.contains('.')), "no sand was thrown up\n{}", frame_rows(&map));
+
+    let result = result.run(300);
+    assert_eq!(result.count(CellType::Sand), sand);
+    result.assert_settled(CellType::Sand);
+}
+
+#[test]
+fn single_explosion_leaves_debris_and_heats_once() {
+    let mut result = scenario(&tall_map(30, 30, &[('#', 10)]));
+    result.grid.place(19 * GRID_WIDTH + 15, &CellType::Explosive);
+    let before = result.grid.cells.temperature[25 * GRID_WIDTH + 15];
+    result.grid.explode((15.0, 19.0), 10.0, 6.0);
+
+    assert_eq!(result.cell(15, 19), CellType::Air);
+    assert!(result.count(CellType::Debris) > 0);
+    // Six cells from the center, outside of the core
+    let heat = result.grid.cells.temperature[25 * GRID_WIDTH + 15] - before;
+    assert!((heat - 600.0 * 0.4).abs() < 0.01, "heated by {heat}");
+}
+
+#[test]
+fn heat_sets_off_chain_of_explosives() {
+    let mut result = scenario("
+        |                              |
+        |                              |
+        |                              |
+        |   !!     !!     !!     !!    |
+        |##############################|
+    ");
+    result.grid.cells.temperature[4 * GRID_WIDTH + 3] = 1000.0;
+    let result = result.run(20);
+
+    assert_eq!(result.count(CellType::Explosive), 0);
+    assert!(result.count(CellType::Debris) > 0);
+}
+
+#[test]
+fn impulse_pushes_cells_away_from_center() {
+    let mut result = scenario(&tall_map(30, 20, &[('.', 1)]));
+    result.grid.impulse((15.0, 19.0), 8.0, 6.0);
+    for x in 8..15 {
+        assert!(result.grid.cells.velocity[19 * GRID_WIDTH + x].0 < 0.0);
+    }
+    for x in 16..23 {
+        assert!(result.grid.cells.velocity[19 * GRID_WIDTH + x].0 > 0.0);
+    }
+
+    let result = result.run(100);
+    assert_eq!(result.count(CellType::Sand), 30);
+    assert!(result.to_map()[19].starts_with(".."), "{}", frame_rows(&result.to_map()));
+}
+
+#[test]
+fn sand_falls_towards_sideways_gravity() {
+    let mut result = scenario("
+        |          |
+        |  ...     |
+        |  ...     |
+        |          |
+    ");
+    result.grid.gravity = (0.3, 0.0);
+    let result = result.run(100);
+
+    assert_eq!(result.count(CellType::Sand), 6);
+    result.assert_settled_towards(CellType::Sand, (1, 0));
+    for row in result.to_map() {
+        assert!(row.starts_with("        "), "sand didn't fall right\n{}", frame_rows(&result.to_map()));
+    }
+}
+
+#[test]
+fn sand_piles_up_on_the_ceiling() {
+    let map = "
+        |                     |
+        |                     |
+        |                     |
+        |                     |
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+        |          .          |
+    ";
+    for seed in 0..4 {
+        let mut result = scenario_with_seed(map, seed);
+        result.grid.gravity = (0.0, -0.3);
+        let result = result.run(200);
+
+        assert_eq!(result.count(CellType::Sand), 6);
+        result.assert_settled_towards(CellType::Sand, (0, -1));
+        let top_row = &result.to_map()[0];
+        assert!(top_row.matches('.').count() > 1, "seed {seed}: sand didn't spread\n{}", frame_rows(&result.to_map()));
+    }
+}
+
+#[test]
+fn sand_floats_without_gravity() {
+    let map = "
+        |          |
+        |   ..     |
+        |          |
+        |          |
+    ";
+    let mut result = scenario(map);
+    result.grid.gravity = (0.0, 0.0);
+    result.run(50).assert_map(map);
+}
+
+#[test]
+fn slow_sand_drifts_by_whole_cells() {
+    let mut result = scenario("
+        |.         |
+        |          |
+    ");
+    result.grid.gravity = (0.0, 0.0);
+    result.grid.cells.velocity[0] = (0.25, 0.0);
+    result.run(12).assert_map("
+        |   .      |
+        |          |
+    ");
+}
+
+#[test]
+fn flying_grain_knocks_another_one_along() {
+    let mut result = scenario("
+        |.    .              |
+        |                    |
+    ");
+    result.grid.gravity = (0.0, 0.0);
+    result.grid.cells.velocity[0] = (3.0, 0.0);
+    let result = result.run(6);
+
+    assert_eq!(result.count(CellType::Sand), 2);
+    let row = &result.to_map()[0];
+    assert!(row.rfind('.').is_some_and(|x| x > 7), "the grain that was hit didn't move\n{}", frame_rows(&result.to_map()));
+}
+
+#[test]
+fn light_script_material_falls_slower_than_sand() {
+    let script = r#"
+        material("test_feather", #{ symbol: "q", movable: true, drag: [0.4, 0.2], terminal_velocity: 0.5 });
+    "#;
+    let mut result = scenario_with_script(script, &tall_map(5, 30, &[]));
+    result.grid.place(1, &CELL_SAND);
+    result.grid.place(3, result.material("test_feather"));
+    let result = result.run(20);
+
+    let height = |symbol: char| (0..result.height).find(|y| result.to_map()[*y].contains(symbol)).unwrap();
+    assert_eq!(height('.'), 29);
+    assert!(height('q') <= 10, "the feather fell {} cells", height('q'));
+    let feather = (0..GRID_SIZE).find(|pos| CellType::get_symbol(result.grid.cells.material(*pos), &result.grid.materials) == 'q').unwrap();
+    assert!(result.grid.cells.velocity[feather].1 <= 0.5);
+}
+
+#[test]
+fn sand_sinks_slowly_through_water() {
+    let result = scenario("
+        |#.#.#|
+        |#~# #|
+        |#~# #|
+        |#~# #|
+        |#~# #|
+        |#~# #|
+        |#~# #|
+        |#~# #|
+        |#~# #|
+        |#~# #|
+        |#####|
+    ").run(8);
+
+    let height = |x: usize| (0..result.height).find(|y| result.cell(x, *y) == CellType::Sand).unwrap();
+    assert_eq!(height(3), 9);
+    assert!((1..6).contains(&height(1)), "sand sank down to {}\n{}", height(1), frame_rows(&result.to_map()));
+}
+
+#[test]
+fn bouncy_script_material_bounces_off_the_floor() {
+    let script = r#"
+        material("test_rubber", #{ symbol: "o", movable: true, restitution: 1.0 });
+    "#;
+    let mut result = scenario_with_script(script, &tall_map(5, 30, &[]));
+    result.grid.place(2, result.material("test_rubber"));
+
+    // Landing slower than it takes to bounce, the ball may roll off to the side
+    let height = |result: &Scenario| (0..result.height).find(|y| result.to_map()[*y].contains('o'));
+    let mut landed = false;
+    let mut highest_bounce = 0;
+    for _ in 0..80 {
+        result = result.run(1);
+        let y = height(&result).unwrap();
+        landed |= y == result.height - 1;
+        if landed {
+            highest_bounce = highest_bounce.max(result.height - 1 - y);
+        }
+    }
+    assert!(highest_bounce >= 3, "bounced up {highest_bounce} cells");
+}
+
+#[test]
+fn wind_blows_falling_sand_aside() {
+    let mut result = scenario(&tall_map(30, 30, &[]));
+    result.grid.place(2, &CELL_SAND);
+    result.grid.add_force_field(ForceField::new((15, 14), (30, 20), Force::Wind((0.5, 0.0))));
+    let result = result.run(100);
+
+    assert_eq!(result.count(CellType::Sand), 1);
+    result.assert_settled(CellType::Sand);
+    let bottom_row = &result.to_map()[29];
+    assert!(bottom_row.find('.').is_some_and(|x| x > 10), "sand wasn't blown aside\n{}", frame_rows(&result.to_map()));
+}
+
+#[test]
+fn wind_pushes_water_and_gas_along() {
+    let box_map = |symbol: char| {
+        let mut rows = vec![format!("|{}|", " ".repeat(20)); 3];
+        rows.append(&mut vec![format!("|{}{}|", symbol.to_string().repeat(6), " ".repeat(14)); 2]);
+        rows.join("\n")
+    };
+    let right_side = |result: &Scenario, cell_type: CellType| {
+        (0..result.height).flat_map(|y| (10..20).map(move |x| (x, y))).filter(|(x, y)| result.cell(*x, *y) == cell_type).count()
+    };
+
+    let mut water = scenario(&box_map('~'));
+    water.grid.add_force_field(ForceField::new((10, 2), (20, 5), Force::Wind((0.5, 0.0))));
+    let water = water.run(60);
+    assert_eq!(water.count(CellType::Water), 12);
+    assert!(right_side(&water, CellType::Water) >= 10, "water wasn't blown aside\n{}", frame_rows(&water.to_map()));
+
+    let mut gas = scenario(&box_map('^'));
+    gas.grid.add_force_field(ForceField::new((10, 2), (20, 5), Force::Wind((0.5, 0.0))));
+    let gas = gas.run(60);
+    assert_eq!(gas.count(CellType::Co2), 12);
+    assert!(right_side(&gas, CellType::Co2) >= 10, "gas wasn't blown aside\n{}", frame_rows(&gas.to_map()));
+}
+
+#[test]
+fn updraft_only_lifts_cells_inside_it() {
+    let mut result = scenario("
+        |          |
+        |          |
+        |          |
+        |          |
+        |          |
+        |.. ..  .. |
+    ");
+    result.grid.add_force_field(ForceField::new((4, 3), (2, 6), Force::Wind((0.0, -0.5))));
+    let result = result.run(30);
+
+    let map = result.to_map();
+    assert_eq!(map[5], "..     .. ", "\n{}", frame_rows(&map));
+    assert_eq!(result.count(CellType::Sand), 6);
+}
+
+#[test]
+fn sand_falls_out_through_void_floor() {
+    let mut result = scenario(&tall_map(10, GRID_WIDTH, &[]));
+    result.grid.place_circle((5, 5), 3, &CELL_SAND);
+    result.grid.boundaries.bottom = Boundary::Void;
+    let result = result.run(120);
+
+    assert_eq!(result.count(CellType::Sand), 0);
+}
+
+#[test]
+fn water_pours_out_through_void_floor() {
+    let mut result = scenario(&tall_map(5, GRID_WIDTH, &[('~', 3)]));
+    result.grid.boundaries.bottom = Boundary::Void;
+    let result = result.run(60);
+
+    assert_eq!(result.count(CellType::Water), 0);
+}
+
+#[test]
+fn sand_wraps_around_to_the_other_side() {
+    // Sideways gravity throws the sand over the left edge, a wall on the right catches it
+    let rows: Vec<String> = (0..5).map(|_| format!("|{}#{}|", " ".repeat(150), " ".repeat(GRID_WIDTH - 151))).collect();
+    let mut result = scenario(&rows.join("\n"));
+    for y in 0..5 {
+        result.grid.place(y * GRID_WIDTH + 2, &CELL_SAND);
+    }
+    result.grid.gravity = (-GRAVITY, 0.0);
+    result.grid.boundaries.left = Boundary::Wrap;
+    result.grid.boundaries.right = Boundary::Wrap;
+    let result = result.run(150);
+
+    assert_eq!(result.count(CellType::Sand), 5);
+    result.assert_settled_towards(CellType::Sand, (-1, 0));
+    for row in result.to_map() {
+        assert!(row[..150].trim().is_empty(), "sand didn't wrap around\n{}", frame_rows(&result.to_map()));
+    }
+}
+
+#[test]
+fn spout_pours_sand_into_a_box() {
+    let result = scenario("
+        |    :     |
+        |          |
+        |          |
+        |          |
+        |          |
+        |          |
+        |          |
+        |          |
+    ").run(120);
+
+    assert_eq!(result.to_map()[7], "..........", "spout didn't fill the box\n{}", frame_rows(&result.to_map()));
+}
+
+#[test]
+fn drain_swallows_loose_cells_but_not_walls() {
+    let result = scenario("
+        |  ......  |
+        |          |
+        |          |
+        |##______##|
+    ");
+    let stone = result.count(CellType::Stone);
+    let result = result.run(60);
+
+    assert_eq!(result.count(CellType::Sand), 0);
+    assert_eq!(result.count(CellType::Drain), 6);
+    assert_eq!(result.count(CellType::Stone), stone);
+}
+
+#[test]
+fn script_materials_can_emit_and_drain() {
+    let script = r#"
+        material("test_tap", #{ symbol: "T", emits: "sand", emit_rate: 1.0 });
+        material("test_sink", #{ symbol: "K", drain: true });
+    "#;
+    let result = scenario_with_script(script, "
+        |    T     |
+        |          |
+        |          |
+        |          |
+        |KKKKKKKKKK|
+    ").run(100);
+
+    // Everything that comes out falls into the sink, apart from what is still on the way
+    assert!(result.count(CellType::Sand) < 10, "sand piled up\n{}", frame_rows(&result.to_map()));
+    assert!(Scripts::load(r#"material("test_bad_tap", #{ symbol: "B", emits: "nothing" });"#).is_err());
+}
+
+#[test]
+fn reaction_turns_touching_cells_into_products() {
+    let script = r#"
+        material("test_acid", #{ symbol: "a", solid: false });
+        reaction(#{ reactants: ["test_acid", "stone"], products: ["air", "sand"] });
+    "#;
+    let result = scenario_with_script(script, "
+        |          |
+        |  aaa     |
+        |##########|
+    ").run(1);
+
+    assert_eq!(result.count(*result.material("test_acid")), 0);
+    assert_eq!(result.count(CellType::Sand), 3);
+}
+
+#[test]
+fn reaction_waits_for_temperature() {
+    let script = r#"
+        material("test_fuse", #{ symbol: "f", solid: false });
+        reaction(#{ reactants: ["test_fuse", "stone"], products: ["air", "stone"], min_temperature: 500.0 });
+    "#;
+    let map = "
+        |          |
+        |    f     |
+        |##########|
+    ";
+    let mut result = scenario_with_script(script, map).run(20);
+    result.assert_map(map);
+
+    result.grid.cells.temperature[GRID_WIDTH + 4] = 600.0;
+    result.run(1).assert_map("
+        |          |
+        |          |
+        |##########|
+    ");
+}
+
+#[test]
+fn hot_coal_burns_away() {
+    let mut result = scenario("
+        |          |
+        |   ***    |
+        |##########|
+    ");
+    for pos in 0..GRID_SIZE {
+        if result.grid.cells.material(pos).eq(&CellType::Coal) {
+            result.grid.cells.temperature[pos] = 400.0;
+        }
+    }
+    let result = result.run(300);
+
+    assert_eq!(result.count(CellType::Coal), 0);
+    assert_eq!(result.count(CellType::Co2), 3);
+}
+
+// Height of the liquid standing in the given column, counted up from the given row
+fn liquid_height(result: &Scenario, x: usize, bottom: usize) -> usize {
+    (0..=bottom).rev().take_while(|y| result.cell(x, *y) == CellType::Water).count()
+}
+
+#[test]
+fn water_levels_even_out_in_a_u_tube() {
+    let result = scenario("
+        |#~~######  #|
+        |#~~######  #|
+        |#~~######  #|
+        |#~~######  #|
+        |#~~######  #|
+        |#~~~~~~~~~~#|
+        |############|
+    ").run(200);
+
+    assert_eq!(result.count(CellType::Water), 20);
+    let left = liquid_height(&result, 1, 5).max(liquid_height(&result, 2, 5));
+    let right = liquid_height(&result, 9, 5).max(liquid_height(&result, 10, 5));
+    assert!(left.abs_diff(right) <= 1, "levels differ\n{}", frame_rows(&result.to_map()));
+}
+
+#[test]
+fn water_column_wrapping_top_to_bottom_stays_put() {
+    // Full-height column, every cell has water on top of it through the wrapped edges
+    let rows: Vec<String> = (0..GRID_WIDTH).map(|_| "|#~#|".to_string()).collect();
+    let mut result = scenario(&rows.join("\n"));
+    result.grid.boundaries.top = Boundary::Wrap;
+    result.grid.boundaries.bottom = Boundary::Wrap;
+    let result = result.run(2);
+
+    assert_eq!(result.count(CellType::Water), GRID_WIDTH);
+    assert!((0..GRID_WIDTH).all(|y| result.cell(1, y) == CellType::Water));
+}
+
+#[test]
+fn water_pressure_grows_with_depth() {
+    let result = scenario("
+        |#    #|
+        |#~~~~#|
+        |#~~~~#|
+        |#~~~~#|
+        |######|
+    ").run(1);
+
+    assert_eq!(result.grid.cells.pressure[GRID_WIDTH + 2], 0.0);
+    assert_eq!(result.grid.cells.pressure[3 * GRID_WIDTH + 2], 2.0);
+}
+
+#[test]
+fn water_settles_into_a_gas_pocket_under_itself() {
+    let mut result = scenario("
+        |#~~~#|
+        |#~#~#|
+        |#~#^#|
+        |#~~~#|
+        |#####|
+    ");
+    result.grid.update_pressure();
+    result.assert_map("
+        |#^~~#|
+        |#~#~#|
+        |#~#~#|
+        |#~~~#|
+        |#####|
+    ");
+}
+
+#[test]
+fn gas_rushes_out_of_an_opened_chamber() {
+    let map = "
+        |            |
+        |            |
+        |            |
+        |#####       |
+        |^^^^#       |
+        |^^^^#       |
+        |^^^^#       |
+        |#####       |
+    ";
+    let sealed = scenario(map).run(30);
+    sealed.assert_map(map);
+
+    let mut opened = scenario(map);
+    opened.grid.cells.set(5 * GRID_WIDTH + 4, Cell::new(&CellType::Air, &opened.grid.materials));
+    let result = opened.run(30);
+    let escaped = (0..result.height).flat_map(|y| (5..result.width).map(move |x| (x, y))).filter(|(x, y)| result.cell(*x, *y) == CellType::Co2).count();
+    assert_eq!(result.count(CellType::Co2), 12);
+    assert!(escaped >= 6, "gas stayed inside\n{}", frame_rows(&result.to_map()));
+}
+
+#[test]
+fn smoke_fades_and_disappears() {
+    let mut result = scenario("
+        |   &&&&   |
+        |          |
+        |          |
+        |##########|
+    ");
+    let color = result.grid.cells.color(3);
+    result = result.run(30);
+    let smoke = (0..GRID_SIZE).find(|pos| result.grid.cells.material(*pos).eq(&CellType::Smoke)).unwrap();
+    assert!(result.grid.cells.faded_color(smoke, &result.grid.materials)[0] < color[0]);
+
+    let result = result.run(120);
+    assert_eq!(result.count(CellType::Smoke), 0);
+}
+
+#[test]
+fn every_grid_has_its_own_script_materials() {
+    let map = "
+        | S |
+        |   |
+        |###|
+    ";
+    let falling = scenario_with_script(r#"material("test_shared", #{ symbol: "S", movable: true });"#, map);
+    let fixed = scenario_with_script(r#"
+        material("test_other", #{ symbol: "O" });
+        material("test_shared", #{ symbol: "S" });
+    "#, map);
+    assert_ne!(falling.material("test_shared"), fixed.material("test_shared"));
+
+    falling.run(10).assert_map("
+        |   |
+        | S |
+        |###|
+    ");
+    fixed.run(10).assert_map(map);
+    assert!(CellType::find("test_shared", &Grid::new(SEED).materials).is_none());
+}
+
+#[test]
+fn moving_smoke_stays_listed_and_other_cells_still_age() {
+    let mut result = scenario("
+        |  &&&&    |
+        |  &&  .   |
+        |          |
+        |##########|
+    ");
+    for _ in 0..20 {
+        result = result.run(3);
+        let cells = &result.grid.cells;
+        assert_eq!(cells.timed().len(), result.count(CellType::Smoke));
+        assert!(cells.timed().iter().all(|pos| cells.material(*pos).eq(&CellType::Smoke)));
+    }
+    let sand = (0..GRID_SIZE).find(|pos| result.grid.cells.material(*pos).eq(&CellType::Sand)).unwrap();
+    assert_eq!(result.grid.cells.age(sand), 60);
+}
+
+#[test]
+fn script_material_decays_into_another() {
+    let script = r#"material("test_spark", #{ symbol: "k", solid: false, lifetime: 5, decays_into: "sand" });"#;
+    let map = "
+        |    k     |
+        |##########|
+    ";
+    let result = scenario_with_script(script, map).run(4);
+    result.assert_map(map);
+    result.run(1).assert_map("
+        |    .     |
+        |##########|
+    ");
+
+    assert!(Scripts::load(r#"material("test_bad_spark", #{ symbol: "j", lifetime: [5, 2] });"#).is_err());
+}
+
+fn terrain_count(grid: &Grid, cell_type: &CellType) -> usize {
+    grid.count_materials()[CellType::index(cell_type)]
+}
+
+#[test]
+fn terrain_is_the_same_for_the_same_seed() {
+    let terrain = Terrain::default();
+    let scene = Grid::from_terrain(7, &terrain).to_scene();
+    assert_eq!(scene, Grid::from_terrain(7, &terrain).to_scene());
+    assert_ne!(scene, Grid::from_terrain(8, &terrain).to_scene());
+}
+
+#[test]
+fn terrain_has_every_layer() {
+    let grid = Grid::from_terrain(7, &Terrain::default());
+    for cell_type in [&CELL_STONE, &CELL_SAND, &CellType::Dirt, &CellType::Water, &CellType::Coal] {
+        assert!(terrain_count(&grid, cell_type) > 0, "no {}", CellType::get_name(cell_type, &grid.materials));
+    }
+
+    // Bedrock at the bottom, and the ground starts with dirt or sand wherever it isn't under water
+    assert!((GRID_SIZE - GRID_WIDTH..GRID_SIZE).all(|pos| grid.cells.material(pos).eq(&CELL_STONE)));
+    for x in 0..GRID_WIDTH {
+        let top = (0..GRID_WIDTH)
+            .map(|y| grid.cells.material(y * GRID_WIDTH + x))
+            .find(|cell_type| !matches!(cell_type, CellType::Air | CellType::Water))
+            .unwrap();
+        assert!(top.eq(&CellType::Dirt) || top.eq(&CELL_SAND), "column {x} starts with {}", CellType::get_name(top, &grid.materials));
+    }
+}
+
+#[test]
+fn terrain_parameters_change_the_mix() {
+    assert_eq!(Terrain::parse("default"), Some(Terrain::default()));
+    assert_eq!(Terrain::parse("dirt=0, caves=0.5").map(|terrain| (terrain.dirt, terrain.caves)), Some((0, 0.5)));
+    assert!(Terrain::parse("lava=1").is_none());
+    assert!(Terrain::parse("dirt=lots").is_none());
+
+    let terrain = Terrain::parse("dirt=0,sand=0,coal=0,water=1").unwrap();
+    let grid = Grid::from_terrain(7, &terrain);
+    for cell_type in [&CELL_SAND, &CellType::Dirt, &CellType::Water, &CellType::Coal] {
+        assert_eq!(terrain_count(&grid, cell_type), 0, "{}", CellType::get_name(cell_type, &grid.materials));
+    }
+
+    // Without caves the ground is solid all the way down
+    let grid = Grid::from_terrain(7, &Terrain::parse("caves=0").unwrap());
+    let caves = Grid::from_terrain(7, &Terrain::default());
+    assert!(terrain_count(&grid, &CellType::Air) < terrain_count(&caves, &CellType::Air));
+}
+
+const FUNNEL_PUZZLE: &str = "
+# Comments and blank lines are skipped
+title: Funnel
+allow: sand 6
+area: 0,0 9,1
+goal: 4 sand in 3,4 6,5
+scene:
+
+
+##      ##
+ ##    ##
+  #    #
+  ######
+";
+
+#[test]
+fn puzzle_is_solved_once_the_goal_is_reached() {
+    let mut attempt = Attempt::new(Puzzle::parse(FUNNEL_PUZZLE, None).unwrap());
+    let mut grid = attempt.start(SEED);
+    assert_eq!(attempt.puzzle.title, "Funnel");
+
+    attempt.place_line(&mut grid, (0, 0), (5, 0), 1, &CELL_SAND);
+    for _ in 0..5 {
+        grid.execute_logic();
+        attempt.tick(&grid);
+    }
+    assert_eq!(attempt.solved_at, None);
+    for _ in 0..40 {
+        grid.execute_logic();
+        attempt.tick(&grid);
+    }
+    assert_eq!(attempt.progress, vec![4]);
+    assert!(attempt.solved_at.is_some_and(|ticks| ticks > 5 && ticks <= attempt.ticks));
+
+    // Starting over brings back the allowance and the empty funnel
+    let grid = attempt.start(SEED);
+    assert_eq!((attempt.remaining.clone(), attempt.progress.clone(), attempt.solved_at), (vec![Some(6)], vec![0], None));
+    assert_eq!(grid.count_materials()[CellType::index(&CELL_SAND)], 0);
+}
+
+#[test]
+fn puzzle_limits_what_can_be_placed() {
+    let mut attempt = Attempt::new(Puzzle::parse(FUNNEL_PUZZLE, None).unwrap());
+    let mut grid = attempt.start(SEED);
+    let count = |grid: &Grid, cell_type: &CellType| grid.count_materials()[CellType::index(cell_type)];
+    let stone = count(&grid, &CELL_STONE);
+
+    // Not allowed, outside of the area, and on top of the funnel
+    attempt.place_line(&mut grid, (0, 0), (9, 0), 2, &CELL_STONE);
+    attempt.place_line(&mut grid, (0, 3), (9, 5), 2, &CELL_SAND);
+    assert!(attempt.allows(&CELL_SAND) && !attempt.allows(&CELL_STONE));
+    assert_eq!((count(&grid, &CELL_SAND), count(&grid, &CELL_STONE)), (0, stone));
+
+    attempt.place_line(&mut grid, (0, 0), (9, 1), 3, &CELL_SAND);
+    assert_eq!(count(&grid, &CELL_SAND), 6);
+    assert_eq!(attempt.remaining, vec![Some(0)]);
+}
+
+#[test]
+fn invalid_puzzles_are_rejected() {
+    let error = |text: &str| Puzzle::parse(text, None).err().map(|err| err.to_string());
+    assert_eq!(error("allow: lava\ngoal: 1 sand in 0,0 1,1\nscene:"), Some("unknown material 'lava' at line 1".to_string()));
+    assert_eq!(error("allow: sand lots\ngoal: 1 sand in 0,0 1,1\nscene:"), Some("can't read line 1: 'allow: sand lots'".to_string()));
+    assert_eq!(error("goal: 1 sand in 0,0 999,1\nscene:"), Some("can't read line 1: 'goal: 1 sand in 0,0 999,1'".to_string()));
+    assert_eq!(error("allow: sand\nscene:"), Some("puzzle has no goals".to_string()));
+    assert_eq!(error("goal: 1 sand in 0,0 1,1"), Some("puzzle has no 'scene:' line".to_string()));
+    // Scene errors point at the line of the puzzle file
+    match Puzzle::parse("goal: 1 sand in 0,0 1,1\nscene:\n\n  ?", None) {
+        Err(PuzzleError::Scene(SceneError::UnknownSymbol { line, column, symbol })) => { assert_eq!((line, column, symbol), (4, 3, '?')); }
+        _ => { panic!("expected an unknown symbol"); }
+    }
+
+    for entry in std::fs::read_dir("puzzles").unwrap() {
+        let path = entry.unwrap().path();
+        assert!(Puzzle::load_file(&path, None).is_ok(), "{}", path.display());
+    }
+}
+
+#[test]
+fn headless_errors_and_stats_name_scripts_safely() {
+    let dir = std::env::temp_dir().join(format!("rusty_sand_headless_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let args = |extra: &[&str]| {
+        let mut args: Vec<String> = ["--headless", "--seed", "1"].iter().map(|arg| arg.to_string()).collect();
+        args.append(&mut extra.iter().map(|arg| arg.to_string()).collect());
+        crate::headless::Options::parse(&args).unwrap().unwrap()
+    };
+
+    let missing = dir.join("missing.rhai");
+    let err = crate::headless::run(&args(&["--script", missing.to_str().unwrap()])).unwrap_err();
+    assert!(err.to_string().contains("missing.rhai"), "{err}");
+
+    let script = dir.join("quoted.rhai");
+    std::fs::write(&script, r#"material("test \"quoted\" \\ name", #{ symbol: "Q", solid: false });"#).unwrap();
+    let stats = dir.join("stats.json");
+    crate::headless::run(&args(&["--script", script.to_str().unwrap(), "--stats", stats.to_str().unwrap()])).unwrap();
+    let json = std::fs::read_to_string(&stats).unwrap();
+    assert!(json.contains(r#""test \"quoted\" \\ name": { "count": 0"#), "{json}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn window_rejects_unknown_and_incomplete_flags() {
+    let args = |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+
+    let valid = args(&["--terrain", "default", "--puzzle", "puzzles/tank.txt"]);
+    assert!(crate::check_window_args(&valid).is_ok());
+    assert_eq!(crate::flag_value(&valid, "--puzzle"), Some("puzzles/tank.txt"));
+    assert_eq!(crate::flag_value(&valid, "--script"), None);
+
+    assert_eq!(crate::check_window_args(&args(&["--scirpt", "a.rhai"])), Err("unknown option --scirpt".to_string()));
+    assert_eq!(crate::check_window_args(&args(&["--puzzle"])), Err("missing value for --puzzle".to_string()));
+}
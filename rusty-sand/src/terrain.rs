@@ -0,0 +1,149 @@
+// Generates a starting landscape from a seed: hills of dirt over stone with sand on the beaches,
+// pools of water in the valleys, caves and coal seams in the stone and a floor of bedrock. The
+// shapes come from value noise, so the same seed and parameters always give the same terrain.
+
+use crate::{CellType, Grid, GRID_WIDTH};
+
+// Added to the seed so every feature gets its own noise
+const CAVE_SEED: u64 = 1;
+const COAL_SEED: u64 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Terrain {
+    pub surface: f32, // Average height of the ground, as a fraction of the grid from the top
+    pub hills: f32, // How far the surface rises and falls around the average, in cells
+    pub sand: usize, // Depth of sand where the ground is under or just above the water
+    pub dirt: usize, // Depth of dirt over the stone
+    pub bedrock: usize, // Rows of solid stone at the bottom that caves don't cut into
+    pub caves: f32, // Share of the stone hollowed out into caves, from 0 to 1
+    pub coal: f32, // Share of the stone turned into coal seams, from 0 to 1
+    pub water: f32 // Water level, as a fraction of the grid from the top
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain {
+            surface: 0.48,
+            hills: 20.0,
+            sand: 4,
+            dirt: 10,
+            bedrock: 4,
+            caves: 0.3,
+            coal: 0.3,
+            water: 0.5
+        }
+    }
+}
+
+impl Terrain {
+    // Either "default" or comma separated overrides like "dirt=12,caves=0.5"
+    pub fn parse(value: &str) -> Option<Terrain> {
+        let mut terrain = Terrain::default();
+        if value.trim() == "default" {
+            return Some(terrain);
+        }
+        for pair in value.split(',') {
+            let (name, value) = pair.split_once('=')?;
+            let value = value.trim();
+            match name.trim() {
+                "surface" => { terrain.surface = value.parse().ok()?; }
+                "hills" => { terrain.hills = value.parse().ok()?; }
+                "sand" => { terrain.sand = value.parse().ok()?; }
+                "dirt" => { terrain.dirt = value.parse().ok()?; }
+                "bedrock" => { terrain.bedrock = value.parse().ok()?; }
+                "caves" => { terrain.caves = value.parse().ok()?; }
+                "coal" => { terrain.coal = value.parse().ok()?; }
+                "water" => { terrain.water = value.parse().ok()?; }
+                _ => { return None; }
+            }
+        }
+        Some(terrain)
+    }
+}
+
+impl Grid {
+    pub fn from_terrain(seed: u64, terrain: &Terrain) -> Grid {
+        let mut grid = Grid::new(seed);
+        let height = GRID_WIDTH as f32;
+        let water_level = (terrain.water * height) as usize;
+        let bedrock = GRID_WIDTH - terrain.bedrock.min(GRID_WIDTH);
+
+        for x in 0..GRID_WIDTH {
+            // Spread out to about -1 to 1, layered noise rarely strays far from the middle
+            let hill = (fractal_noise(seed, x as f32 / 48.0, 0.0, 4) - 0.5) * 3.0;
+            let surface = (terrain.surface * height + hill * terrain.hills).clamp(0.0, height) as usize;
+            // Beaches and the floors of pools are sand, everything higher up is dirt
+            let top = if surface + terrain.sand >= water_level { &CellType::Sand } else { &CellType::Dirt };
+
+            for y in 0..GRID_WIDTH {
+                let cell_type = if y >= bedrock {
+                    &CellType::Stone
+                } else if y < surface {
+                    if y >= water_level { &CellType::Water } else { continue; }
+                } else if y < surface + terrain.sand && top.eq(&CellType::Sand) {
+                    &CellType::Sand
+                } else if y < surface + terrain.dirt {
+                    &CellType::Dirt
+                } else {
+                    stone_at(seed, terrain, x, y)
+                };
+                if !cell_type.eq(&CellType::Air) {
+                    grid.place(y * GRID_WIDTH + x, cell_type);
+                }
+            }
+        }
+
+        grid
+    }
+}
+
+// Caves and coal seams follow the lines where their noise crosses the middle, so they come out as
+// winding tunnels and long bands instead of round blobs
+fn stone_at(seed: u64, terrain: &Terrain, x: usize, y: usize) -> &'static CellType {
+    let cave = fractal_noise(seed.wrapping_add(CAVE_SEED), x as f32 / 32.0, y as f32 / 20.0, 3);
+    if (cave - 0.5).abs() < terrain.caves * 0.1 {
+        return &CellType::Air;
+    }
+    // Stretched sideways, seams run roughly horizontal
+    let coal = fractal_noise(seed.wrapping_add(COAL_SEED), x as f32 / 40.0, y as f32 / 8.0, 2);
+    if (coal - 0.5).abs() < terrain.coal * 0.08 {
+        return &CellType::Coal;
+    }
+    &CellType::Stone
+}
+
+// Several layers of noise, each twice as detailed and half as strong as the one before, from 0 to 1
+fn fractal_noise(seed: u64, x: f32, y: f32, octaves: u32) -> f32 {
+    let mut total = 0.0;
+    let mut strength = 1.0;
+    let mut scale = 1.0;
+    for octave in 0..octaves {
+        total += value_noise(seed.wrapping_add(octave as u64 * 1000), x * scale, y * scale) * strength;
+        strength *= 0.5;
+        scale *= 2.0;
+    }
+    total / (2.0 - 2.0 * strength)
+}
+
+// Random values at whole coordinates, smoothly blended in between
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let corner = |dx: i64, dy: i64| lattice_value(seed, x0 as i64 + dx, y0 as i64 + dy);
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+// Hashes the point with the splitmix64 finalizer into a value from 0 to 1
+fn lattice_value(seed: u64, x: i64, y: i64) -> f32 {
+    let mut hash = seed
+        .wrapping_mul(0xD6E8_FEB8_6659_FD93)
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^= hash >> 31;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
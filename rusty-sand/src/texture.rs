@@ -0,0 +1,79 @@
+// Position-based patterns applied on top of a material's palette color when a cell is placed.
+// The pattern only depends on the position, so painting over the same area gives the same result.
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Texture {
+    None,
+    // Wavy horizontal bands of slightly different brightness
+    Strata,
+    // Sparse lighter specks
+    Speckle
+}
+
+const STRATA_THICKNESS: f32 = 3.0;
+const STRATA_WAVE_LENGTH: f32 = 24.0;
+const STRATA_WAVE_HEIGHT: f32 = 4.0;
+const SPECKLE_CHANCE: f32 = 0.06;
+
+impl Texture {
+    pub fn apply(&self, color: [u8;4], pos: (usize, usize)) -> [u8;4] {
+        match self {
+            Texture::None => { color }
+            Texture::Strata => {
+                let wave = smooth_noise(pos.0 as f32 / STRATA_WAVE_LENGTH, 0) * STRATA_WAVE_HEIGHT;
+                let band = ((pos.1 as f32 + wave) / STRATA_THICKNESS).floor() as i32;
+                let shade = 0.8 + 0.35 * hash_noise(band, 1);
+                scale_color(color, shade)
+            }
+            Texture::Speckle => {
+                if hash_noise(pos.0 as i32, pos.1 as i32) < SPECKLE_CHANCE {
+                    scale_color(color, 1.6)
+                } else {
+                    color
+                }
+            }
+        }
+    }
+}
+
+pub fn offset_color(color: [u8;4], offset: i16) -> [u8;4] {
+    let mut result = color;
+    for channel in &mut result[0..3] {
+        *channel = (*channel as i16 + offset).clamp(0, 255) as u8;
+    }
+    result
+}
+
+pub fn scale_color(color: [u8;4], factor: f32) -> [u8;4] {
+    let mut result = color;
+    for channel in &mut result[0..3] {
+        *channel = (*channel as f32 * factor).clamp(0.0, 255.0) as u8;
+    }
+    result
+}
+
+// Mixes the color into a pixel of the frame, for overlays drawn on top of the grid
+pub fn blend_color(pixel: &mut [u8], color: [u8;4], opacity: f32) {
+    for channel in 0..3 {
+        pixel[channel] = (pixel[channel] as f32 + (color[channel] as f32 - pixel[channel] as f32) * opacity) as u8;
+    }
+}
+
+// Stable pseudo random value in 0.0..1.0 for a pair of integers
+fn hash_noise(x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4_eb2d) ^ (y as u32).wrapping_mul(0x1656_67b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    (h & 0xffff) as f32 / 65536.0
+}
+
+// One dimensional value noise, smoothly interpolated between integer points
+fn smooth_noise(x: f32, seed: i32) -> f32 {
+    let x0 = x.floor();
+    let t = x - x0;
+    let t = t * t * (3.0 - 2.0 * t);
+    let a = hash_noise(x0 as i32, seed);
+    let b = hash_noise(x0 as i32 + 1, seed);
+    a + (b - a) * t
+}